@@ -1,4 +1,10 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // push_msg.lua 通过 include_str! 嵌入, 修改后需要重新编译
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=period.capnp");
+    println!("cargo:rerun-if-changed=period.proto");
+    println!("cargo:rerun-if-changed=push_msg.lua");
+
     // 生成 Cap'n Proto 代码
     capnpc::CompilerCommand::new()
         .file("period.capnp")
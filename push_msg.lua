@@ -3,6 +3,15 @@ local new_id = ARGV[1]
 local new_info_count = tonumber(ARGV[2])
 local new_msg_content = ARGV[3]
 local max_stream_size = tonumber(ARGV[4]) or 1000 
+local codec = ARGV[5] or "capnp"
+-- 主备模式下 KEYS[2] 为 fencing token key, ARGV[6] 为本实例持有的 token
+local fence_key = KEYS[2]
+local fence_token = ARGV[6]
+-- ARGV[7] 为条目类型: data 为行情数据; gap 为确认缺失的 period, 之后到达的
+-- 行情数据会替换它; heartbeat 为上游断流时的心跳标记, 此时 KEYS[1] 为心跳
+-- Stream, ARGV[1] 为最后收到的 period, ARGV[3] 为告警原因; gap_range 为一次缺失
+-- 过多 period 时的范围标记, ARGV[1] 和 ARGV[3] 为首尾 period
+local kind = ARGV[7] or "data"
 
 local logs = {}
 local function log(msg)
@@ -14,6 +23,27 @@ log("new_id: " .. new_id)
 log("new_info_count: " .. new_info_count)
 log("msg_content_length: " .. string.len(new_msg_content))
 log("max_stream_size: " .. max_stream_size)
+log("codec: " .. codec)
+log("kind: " .. kind)
+
+if fence_key then
+    local current_token = redis.call("GET", fence_key)
+    if current_token ~= fence_token then
+        log("fencing token 不匹配: 当前=" .. tostring(current_token) .. ", 本实例=" .. tostring(fence_token))
+        return "FENCED\n" .. table.concat(logs, "\n")
+    end
+end
+
+-- 心跳标记写入单独的心跳 Stream (KEYS[1]), 按 MAXLEN 近似裁剪, 不占用行情 Stream 的容量
+if kind == "heartbeat" then
+    redis.call("XADD", stream, "MAXLEN", "~", max_stream_size, "*",
+        "operation", "HEARTBEAT",
+        "last_period", new_id,
+        "reason", new_msg_content
+    )
+    log("写入心跳标记, last_period: " .. new_id)
+    return "HEARTBEAT\n" .. table.concat(logs, "\n")
+end
 
 local current_size = redis.call("XLEN", stream)
 log("当前Stream大小: " .. current_size)
@@ -27,6 +57,18 @@ if current_size >= max_stream_size then
 end
 log("Stream 数量小于限制，不进行清理")
 
+-- 范围标记不带 key 字段, 不参与 period 去重; 范围内迟到的行情数据照常写入
+if kind == "gap_range" then
+    redis.call("XADD", stream, "*",
+        "operation", "GAP_RANGE",
+        "first_period", new_id,
+        "last_period", new_msg_content,
+        "codec", codec
+    )
+    log("写入范围缺口标记: " .. new_id .. ".." .. new_msg_content)
+    return "GAP_RANGE\n" .. table.concat(logs, "\n")
+end
+
 -- 1. 提取 period (如 "100")
 local dash_pos = string.find(new_id, "-")
 if not dash_pos then
@@ -39,17 +81,21 @@ local new_period = string.sub(new_id, dash_pos + 1)
 local msgs = redis.call("XREVRANGE", stream, "+", "-", "COUNT", 100)  -- 查最新 100 条，确保不遗漏
 log("查询到的消息数量: " .. #msgs)
 local max_info_count = 0
+local has_data = false
 local same_period_msgs = {}  -- 存储相同period的所有消息
 
 for i, msg in ipairs(msgs) do
     local fields = msg[2]
     -- Redis Stream字段是以数组形式存储的: [field1, value1, field2, value2, ...]
     local msg_key = nil
+    local msg_operation = nil
     for j = 1, #fields, 2 do
         local field_name = fields[j]
         local field_value = fields[j + 1]
         if field_name == "key" then
             msg_key = field_value
+        elseif field_name == "operation" then
+            msg_operation = field_value
         end
     end
     if msg_key then
@@ -66,6 +112,12 @@ for i, msg in ipairs(msgs) do
                         break
                     end
                 end
+                -- 缺口标记排在任何行情数据之后
+                if msg_operation == "GAP" then
+                    current_count = -1
+                else
+                    has_data = true
+                end
                 log("旧消息的 info_count: " .. current_count)
                 
                 -- 记录这个相同period的消息
@@ -109,8 +161,29 @@ else
     log("没有找到相同period的消息")
 end
 
+-- 缺口标记只在该 period 没有任何条目时写入
+if kind == "gap" then
+    if #same_period_msgs > 0 then
+        log("period 已有条目, 不写入缺口标记")
+        return "EXISTS\n" .. table.concat(logs, "\n")
+    end
+    redis.call("XADD", stream, "*",
+        "operation", "GAP",
+        "key", new_id,
+        "info_count", 0,
+        "msg_content", "",
+        "codec", codec,
+        "replaced_count", 0
+    )
+    log("写入缺口标记: " .. new_id)
+    return "GAP\n" .. table.concat(logs, "\n")
+end
+
+-- 只有缺口标记时, 任何行情数据都替换它
+local only_gaps = #same_period_msgs > 0 and not has_data
+
 -- 3. 判断是否要更新
-if new_info_count > max_info_count then
+if new_info_count > max_info_count or only_gaps then
     -- 删除所有相同period的旧消息
     for _, msg_info in ipairs(same_period_msgs) do
         log("删除旧消息以便更新: " .. msg_info.id)
@@ -124,6 +197,7 @@ if new_info_count > max_info_count then
         "key", new_id,
         "info_count", new_info_count,
         "msg_content", new_msg_content,
+        "codec", codec,
         "replaced_count", #same_period_msgs
     )
     local log_string = table.concat(logs, "\n")
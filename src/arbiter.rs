@@ -0,0 +1,284 @@
+// 双路行情仲裁和延迟择优: 同一 period 只保留信息最全的一份, 并且只转发一次
+use bytes::Bytes;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::config::ArbiterConfig;
+use crate::view::PeriodSummary;
+
+// 记住最近转发过的 period 数量, 用于丢弃迟到的副本
+const FORWARDED_HISTORY: usize = 1024;
+
+/// 仲裁后选出的一份 period, `raw` 是收到的原始数据
+pub struct ArbitratedPeriod {
+    pub summary: PeriodSummary,
+    pub raw: Bytes,
+}
+
+struct Candidate {
+    deadline: Instant,
+    best: ArbitratedPeriod,
+    posters: Vec<String>,
+}
+
+pub struct PeriodArbiter {
+    name: String,
+    // 收齐这么多个不同 poster 的副本后立即转发, None 表示总是等到窗口结束。
+    // 单个 SUB socket 区分不出副本来自哪一路, 只能按 poster_id 区分
+    expected_feeds: Option<usize>,
+    window: Duration,
+    poster_priority: Vec<String>,
+    pending: BTreeMap<i64, Candidate>,
+    forwarded: BTreeSet<i64>,
+    late_dropped: u64,
+    // 已提示过同一 poster 重复送达, 只提示一次
+    warned_same_poster: bool,
+}
+
+impl PeriodArbiter {
+    /// `expected_feeds` 个行情源都送达, 或首次收到后超过 `window_ms` 时转发
+    pub fn new(name: &str, expected_feeds: Option<usize>, cfg: &ArbiterConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            expected_feeds,
+            window: Duration::from_millis(cfg.window_ms),
+            poster_priority: cfg.poster_priority.clone(),
+            pending: BTreeMap::new(),
+            forwarded: BTreeSet::new(),
+            late_dropped: 0,
+            warned_same_poster: false,
+        }
+    }
+
+    /// 放入收到的一份 period, 已经可以转发时返回选出的版本
+    pub fn offer(&mut self, summary: PeriodSummary, raw: Bytes) -> Option<ArbitratedPeriod> {
+        let period = summary.period;
+        // 历史已满时, 比最早记录还旧的 period 也视为已转发
+        let beyond_history = self.forwarded.len() >= FORWARDED_HISTORY
+            && self.forwarded.first().is_some_and(|first| period < *first);
+        if beyond_history || self.forwarded.contains(&period) {
+            self.late_dropped += 1;
+            debug!(
+                "[{}] period {} 已转发, 丢弃来自 {} 的迟到副本, 累计: {}",
+                self.name, period, summary.poster_id, self.late_dropped
+            );
+            return None;
+        }
+
+        let info_count = summary.info_count();
+        let poster_id = summary.poster_id.clone();
+        let incoming = ArbitratedPeriod { summary, raw };
+
+        let candidate = match self.pending.get_mut(&period) {
+            Some(candidate) => {
+                if is_better(&self.poster_priority, &incoming, &candidate.best) {
+                    debug!(
+                        "[{}] period {} 改用 {} 的版本, info_count: {} -> {}",
+                        self.name, period, poster_id, candidate.best.summary.info_count(), info_count
+                    );
+                    candidate.best = incoming;
+                }
+                if !candidate.posters.contains(&poster_id) {
+                    candidate.posters.push(poster_id);
+                } else if self.expected_feeds.is_some() && !self.warned_same_poster {
+                    self.warned_same_poster = true;
+                    warn!(
+                        "[{}] period {} 收到 {} 的多份副本, 两路行情的 poster_id 相同时无法提前转发, 每个 period 都要等满窗口",
+                        self.name, period, poster_id
+                    );
+                }
+                candidate
+            }
+            None => self.pending.entry(period).or_insert(Candidate {
+                deadline: Instant::now() + self.window,
+                best: incoming,
+                posters: vec![poster_id],
+            }),
+        };
+
+        if self
+            .expected_feeds
+            .is_some_and(|expected| candidate.posters.len() >= expected)
+        {
+            return self.forward(period);
+        }
+        None
+    }
+
+    /// 最近一个等待窗口的截止时间
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|c| c.deadline).min()
+    }
+
+    /// 取出等待窗口已结束的 period, 按 period 升序
+    pub fn take_expired(&mut self, now: Instant) -> Vec<ArbitratedPeriod> {
+        let expired: Vec<i64> = self
+            .pending
+            .iter()
+            .filter(|(_, c)| c.deadline <= now)
+            .map(|(period, _)| *period)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|period| self.forward(period))
+            .collect()
+    }
+
+    /// 关闭时取出全部等待中的 period
+    pub fn take_all(&mut self) -> Vec<ArbitratedPeriod> {
+        let periods: Vec<i64> = self.pending.keys().copied().collect();
+        periods
+            .into_iter()
+            .filter_map(|period| self.forward(period))
+            .collect()
+    }
+
+    pub fn late_dropped(&self) -> u64 {
+        self.late_dropped
+    }
+
+    fn forward(&mut self, period: i64) -> Option<ArbitratedPeriod> {
+        let candidate = self.pending.remove(&period)?;
+        info!(
+            "[{}] period {} 选用 {} 的版本, info_count: {}, 收到的行情源: {:?}",
+            self.name, period, candidate.best.summary.poster_id, candidate.best.summary.info_count(), candidate.posters
+        );
+        self.forwarded.insert(period);
+        while self.forwarded.len() > FORWARDED_HISTORY {
+            self.forwarded.pop_first();
+        }
+        Some(candidate.best)
+    }
+}
+
+// info_count 更大者优先, 相同时按 poster_priority 比较, 仍相同时保留先到的
+fn is_better(poster_priority: &[String], incoming: &ArbitratedPeriod, current: &ArbitratedPeriod) -> bool {
+    let (incoming_count, current_count) = (incoming.summary.info_count(), current.summary.info_count());
+    if incoming_count != current_count {
+        return incoming_count > current_count;
+    }
+    priority_of(poster_priority, &incoming.summary.poster_id) < priority_of(poster_priority, &current.summary.poster_id)
+}
+
+fn priority_of(poster_priority: &[String], poster_id: &str) -> usize {
+    poster_priority
+        .iter()
+        .position(|p| p == poster_id)
+        .unwrap_or(poster_priority.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(period: i64, poster_id: &str, inc_count: u64) -> PeriodSummary {
+        PeriodSummary {
+            period,
+            ts: period * 3000,
+            post_ts: period * 3000,
+            poster_id: poster_id.to_string(),
+            symbol_count: 1,
+            trade_count: 0,
+            inc_count,
+        }
+    }
+
+    fn arbiter(expected_feeds: Option<usize>, poster_priority: &[&str]) -> PeriodArbiter {
+        let cfg = ArbiterConfig {
+            window_ms: 1000,
+            poster_priority: poster_priority.iter().map(|p| p.to_string()).collect(),
+        };
+        PeriodArbiter::new("test", expected_feeds, &cfg)
+    }
+
+    fn offer(arbiter: &mut PeriodArbiter, period: i64, poster_id: &str, inc_count: u64) -> Option<ArbitratedPeriod> {
+        arbiter.offer(summary(period, poster_id, inc_count), Bytes::from(poster_id.to_string()))
+    }
+
+    #[test]
+    fn forwards_once_all_feeds_arrive() {
+        let mut arbiter = arbiter(Some(2), &[]);
+        assert!(offer(&mut arbiter, 1, "a", 10).is_none());
+        let chosen = offer(&mut arbiter, 1, "b", 12).unwrap();
+        assert_eq!(chosen.summary.poster_id, "b");
+        assert_eq!(chosen.raw, Bytes::from("b"));
+        assert!(arbiter.next_deadline().is_none());
+    }
+
+    #[test]
+    fn same_poster_waits_for_window() {
+        let mut arbiter = arbiter(Some(2), &[]);
+        assert!(offer(&mut arbiter, 1, "a", 10).is_none());
+        assert!(offer(&mut arbiter, 1, "a", 11).is_none());
+
+        let deadline = arbiter.next_deadline().unwrap();
+        assert!(arbiter.take_expired(deadline - Duration::from_millis(1)).is_empty());
+        let expired = arbiter.take_expired(deadline);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].summary.info_count(), 11);
+    }
+
+    #[test]
+    fn take_expired_in_period_order() {
+        let mut arbiter = arbiter(None, &[]);
+        offer(&mut arbiter, 2, "a", 1);
+        offer(&mut arbiter, 1, "a", 1);
+        let expired = arbiter.take_expired(Instant::now() + Duration::from_secs(2));
+        let periods: Vec<i64> = expired.iter().map(|p| p.summary.period).collect();
+        assert_eq!(periods, vec![1, 2]);
+        assert!(arbiter.next_deadline().is_none());
+    }
+
+    #[test]
+    fn tie_break_by_poster_priority() {
+        let mut arbiter = arbiter(Some(2), &["b", "a"]);
+        offer(&mut arbiter, 1, "a", 10);
+        assert_eq!(offer(&mut arbiter, 1, "b", 10).unwrap().summary.poster_id, "b");
+
+        offer(&mut arbiter, 2, "b", 10);
+        assert_eq!(offer(&mut arbiter, 2, "a", 10).unwrap().summary.poster_id, "b");
+    }
+
+    #[test]
+    fn tie_without_priority_keeps_first() {
+        let mut arbiter = arbiter(Some(2), &[]);
+        offer(&mut arbiter, 1, "a", 10);
+        assert_eq!(offer(&mut arbiter, 1, "b", 10).unwrap().summary.poster_id, "a");
+    }
+
+    #[test]
+    fn late_duplicate_dropped() {
+        let mut arbiter = arbiter(Some(2), &[]);
+        offer(&mut arbiter, 1, "a", 10);
+        offer(&mut arbiter, 1, "b", 10).unwrap();
+        assert!(offer(&mut arbiter, 1, "c", 20).is_none());
+        assert_eq!(arbiter.late_dropped(), 1);
+        assert!(arbiter.next_deadline().is_none());
+
+        // 窗口结束后才到的副本同样丢弃
+        offer(&mut arbiter, 2, "a", 10);
+        assert_eq!(arbiter.take_expired(Instant::now() + Duration::from_secs(2)).len(), 1);
+        assert!(offer(&mut arbiter, 2, "b", 20).is_none());
+        assert_eq!(arbiter.late_dropped(), 2);
+    }
+
+    #[test]
+    fn older_than_history_dropped() {
+        let mut arbiter = arbiter(Some(1), &[]);
+        for period in 1..=FORWARDED_HISTORY as i64 + 1 {
+            assert!(offer(&mut arbiter, period, "a", 1).is_some());
+        }
+        assert!(offer(&mut arbiter, 1, "a", 1).is_none());
+        assert_eq!(arbiter.late_dropped(), 1);
+    }
+
+    #[test]
+    fn take_all_flushes_pending() {
+        let mut arbiter = arbiter(None, &[]);
+        offer(&mut arbiter, 1, "a", 1);
+        offer(&mut arbiter, 2, "a", 1);
+        assert_eq!(arbiter.take_all().len(), 2);
+        assert!(arbiter.take_all().is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+// FromStart 模式下的补数据逻辑: 从本地归档目录读取历史 period 并补发到 Stream
+use anyhow::Result;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{summarize, ExchangeConfig, RedisStreamMktPubber};
+
+/// 归档中的一个 period 文件, 内容在补发时才读取
+pub struct ArchivedPeriod {
+    pub period: i64,
+    pub path: PathBuf,
+}
+
+impl ArchivedPeriod {
+    pub fn read(&self) -> Result<Vec<u8>> {
+        Ok(fs::read(&self.path)?)
+    }
+}
+
+// 归档文件名形如 15215050.capnp 或 15215050.capnp.gz
+fn period_from_file_name(path: &Path) -> Option<i64> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name
+        .strip_suffix(".capnp.gz")
+        .or_else(|| name.strip_suffix(".capnp"))?;
+    stem.parse::<i64>().ok()
+}
+
+/// 列出归档目录下所有 period 文件, 按 period 升序返回; 只按文件名解析 period, 不读取内容
+///
+/// 同一个 period 同时存在 `.capnp` 和 `.capnp.gz` 时只保留先列出的那一份。
+pub fn list_archive(dir: &str) -> Result<Vec<ArchivedPeriod>> {
+    let mut periods: BTreeMap<i64, ArchivedPeriod> = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(period) = period_from_file_name(&path) else {
+            continue;
+        };
+        if periods.contains_key(&period) {
+            continue;
+        }
+        periods.insert(period, ArchivedPeriod { period, path });
+    }
+    Ok(periods.into_values().collect())
+}
+
+/// 把交易所归档中比 Stream 最新 period 更新的数据补发到 Redis, 返回补发条数
+///
+/// 最多补发最新的 `max_stream_size` 个 period, 更早的补发后也会被 Stream 的 FIFO 清理掉。
+pub async fn backfill_from_archive(publisher: &RedisStreamMktPubber, exchange: &ExchangeConfig) -> Result<usize> {
+    let dir = &exchange.archive_dir;
+    let last_period = publisher.last_published_period(&exchange.name).await?;
+    match last_period {
+        Some(p) => info!("[{}] Stream 中最新的 period: {}", exchange.name, p),
+        None => info!("[{}] Stream 为空, 从归档起点开始补数据", exchange.name),
+    }
+
+    let archive = match list_archive(dir) {
+        Ok(a) => a,
+        Err(e) => {
+            warn!("读取归档目录 {} 失败: {}, 跳过补数据", dir, e);
+            return Ok(0);
+        }
+    };
+
+    let pending: Vec<_> = archive
+        .into_iter()
+        .filter(|a| last_period.is_none_or(|last| a.period > last))
+        .collect();
+    let skip = pending.len().saturating_sub(exchange.max_stream_size);
+    if skip > 0 {
+        warn!(
+            "[{}] 待补发 {} 个 period, 超过 max_stream_size {}, 跳过最早的 {} 个",
+            exchange.name,
+            pending.len(),
+            exchange.max_stream_size,
+            skip
+        );
+    }
+
+    let mut published = 0;
+    for archived in pending.into_iter().skip(skip) {
+        let data = match archived.read() {
+            Ok(d) => d,
+            Err(e) => {
+                warn!("读取归档文件 {} 失败: {}", archived.path.display(), e);
+                continue;
+            }
+        };
+        let summary = match summarize(&data, true, &publisher.config().decode, false) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("解析归档文件 {} 失败: {}", archived.path.display(), e);
+                continue;
+            }
+        };
+        let archive_msg = match publisher.build_archive_msg(&exchange.name, &summary, data) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("转码归档文件 {} 失败: {}", archived.path.display(), e);
+                continue;
+            }
+        };
+        // 备实例的 publish 不写入, 不计入补发条数
+        let leader = publisher.is_leader(&exchange.name);
+        if let Err(e) = publisher.publish(&exchange.name, archive_msg).await {
+            warn!("补发归档文件 {} 失败: {}", archived.path.display(), e);
+            continue;
+        }
+        if leader {
+            published += 1;
+        }
+    }
+    info!("[{}] 归档补数据完成, 共补发 {} 个 period", exchange.name, published);
+    Ok(published)
+}
@@ -0,0 +1,328 @@
+// 按 incs 重建盘口: 快照替换整个盘口, 增量按档位修改, 数量为 0 删除档位
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::message::{BookUpdate, IncrementOrderBookInfo, LevelAction, PeriodMessage, PriceLevel, SymbolInfo};
+
+/// 可作为 BTreeMap 键的价格, 按 `f64::total_cmp` 排序
+#[derive(Debug, Clone, Copy)]
+pub struct Price(pub f64);
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// 单个 symbol 的盘口
+///
+/// 同一个 `OrderBook` 可以连续应用多个 period 的 incs; 收到第一个快照之前
+/// 只有增量修改过的档位, 此时 [`has_snapshot`](Self::has_snapshot) 为 false。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderBook {
+    bids: BTreeMap<Price, f64>,
+    asks: BTreeMap<Price, f64>,
+    has_snapshot: bool,
+    // 最后应用的 inc 的时间戳
+    last_ts: Option<i64>,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, inc: &IncrementOrderBookInfo) {
+        let update = inc.update();
+        if let BookUpdate::Snapshot { .. } = update {
+            self.bids.clear();
+            self.asks.clear();
+            self.has_snapshot = true;
+        }
+        apply_levels(&mut self.bids, update.bids());
+        apply_levels(&mut self.asks, update.asks());
+        self.last_ts = Some(inc.timestamp);
+    }
+
+    /// 按顺序应用一个 symbol 在该 period 内的全部 incs
+    pub fn apply_symbol(&mut self, info: &SymbolInfo) {
+        for inc in &info.incs {
+            self.apply(inc);
+        }
+    }
+
+    /// 从当前状态开始, 应用 `info` 中时间戳不晚于 `ts` 的 incs, 返回该时刻的盘口
+    ///
+    /// 当前状态应为该 period 开始时的盘口, 即已应用完前一个 period。
+    pub fn state_at(&self, info: &SymbolInfo, ts: i64) -> OrderBook {
+        let mut book = self.clone();
+        for inc in info.incs.iter().take_while(|inc| inc.timestamp <= ts) {
+            book.apply(inc);
+        }
+        book
+    }
+
+    pub fn has_snapshot(&self) -> bool {
+        self.has_snapshot
+    }
+
+    pub fn last_ts(&self) -> Option<i64> {
+        self.last_ts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// 买一; [`has_snapshot`](Self::has_snapshot) 为 false 时只反映增量修改过的档位,
+    /// 不代表真实的买一, 下面的卖一, 价差和中间价同理
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.bids.last_key_value().map(to_level)
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.asks.first_key_value().map(to_level)
+    }
+
+    /// 卖一减买一, 任一侧为空时为 None
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.best_ask()?.price - self.best_bid()?.price)
+    }
+
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.best_ask()?.price + self.best_bid()?.price) / 2.0)
+    }
+
+    /// 买一卖一交叉或相等, 通常说明缺少快照或丢了增量
+    pub fn is_crossed(&self) -> bool {
+        self.spread().is_some_and(|s| s <= 0.0)
+    }
+
+    /// 价格从高到低的前 `n` 档买盘
+    pub fn top_bids(&self, n: usize) -> Vec<PriceLevel> {
+        self.bids.iter().rev().take(n).map(to_level).collect()
+    }
+
+    /// 价格从低到高的前 `n` 档卖盘
+    pub fn top_asks(&self, n: usize) -> Vec<PriceLevel> {
+        self.asks.iter().take(n).map(to_level).collect()
+    }
+
+    pub fn bid_depth(&self) -> usize {
+        self.bids.len()
+    }
+
+    pub fn ask_depth(&self) -> usize {
+        self.asks.len()
+    }
+}
+
+fn apply_levels(side: &mut BTreeMap<Price, f64>, levels: &[PriceLevel]) {
+    for level in levels {
+        match level.action() {
+            LevelAction::Set(amount) => {
+                side.insert(Price(level.price), amount);
+            }
+            LevelAction::Delete => {
+                side.remove(&Price(level.price));
+            }
+        }
+    }
+}
+
+fn to_level((price, amount): (&Price, &f64)) -> PriceLevel {
+    PriceLevel {
+        price: price.0,
+        amount: *amount,
+    }
+}
+
+/// 一个行情源全部 symbol 的盘口, 按 period 顺序应用
+#[derive(Debug, Clone, Default)]
+pub struct OrderBooks {
+    books: HashMap<String, OrderBook>,
+    last_period: Option<i64>,
+}
+
+impl OrderBooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 应用一个 period 的全部 symbol, 返回该 period 是否紧接上一个 period
+    ///
+    /// 不连续时盘口仍照常更新, 但缺失 period 中的增量已丢失, 直到下一个快照前可能不准确。
+    pub fn apply_period(&mut self, msg: &PeriodMessage) -> bool {
+        let contiguous = self.advance(msg.period);
+        for info in &msg.symbol_infos {
+            self.book_mut(&info.symbol).apply_symbol(info);
+        }
+        contiguous
+    }
+
+    /// 记录即将应用的 period, 返回它是否紧接上一个 period; 需要逐条处理 incs 时
+    /// 先调用它, 再通过 [`book_mut`](Self::book_mut) 更新各 symbol
+    pub fn advance(&mut self, period: i64) -> bool {
+        let contiguous = self.last_period.is_none_or(|last| period == last + 1);
+        self.last_period = Some(period);
+        contiguous
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> &mut OrderBook {
+        if !self.books.contains_key(symbol) {
+            self.books.insert(symbol.to_string(), OrderBook::new());
+        }
+        self.books.get_mut(symbol).unwrap()
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(symbol)
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.books.keys().map(String::as_str)
+    }
+
+    pub fn last_period(&self) -> Option<i64> {
+        self.last_period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(levels: &[(f64, f64)]) -> Vec<PriceLevel> {
+        levels.iter().map(|&(price, amount)| PriceLevel { price, amount }).collect()
+    }
+
+    fn inc(timestamp: i64, is_snapshot: bool, bids: &[(f64, f64)], asks: &[(f64, f64)]) -> IncrementOrderBookInfo {
+        IncrementOrderBookInfo {
+            timestamp,
+            is_snapshot,
+            bids: levels(bids),
+            asks: levels(asks),
+        }
+    }
+
+    fn symbol(incs: Vec<IncrementOrderBookInfo>) -> SymbolInfo {
+        SymbolInfo {
+            symbol: "BTC".to_string(),
+            trades: vec![],
+            incs,
+        }
+    }
+
+    #[test]
+    fn snapshot_replaces_the_whole_book() {
+        let mut book = OrderBook::new();
+        book.apply(&inc(1, false, &[(99.0, 1.0)], &[(101.0, 1.0)]));
+        assert!(!book.has_snapshot());
+
+        book.apply(&inc(2, true, &[(100.0, 2.0)], &[(102.0, 3.0)]));
+        assert!(book.has_snapshot());
+        assert_eq!(book.top_bids(10), levels(&[(100.0, 2.0)]));
+        assert_eq!(book.top_asks(10), levels(&[(102.0, 3.0)]));
+        assert_eq!(book.last_ts(), Some(2));
+    }
+
+    #[test]
+    fn zero_amount_deletes_the_level() {
+        let mut book = OrderBook::new();
+        book.apply(&inc(1, true, &[(100.0, 1.0), (99.0, 1.0)], &[(101.0, 1.0)]));
+        book.apply(&inc(2, false, &[(100.0, 0.0)], &[(101.0, 0.0)]));
+        assert_eq!(book.best_bid(), Some(PriceLevel { price: 99.0, amount: 1.0 }));
+        assert_eq!(book.best_ask(), None);
+        assert_eq!(book.bid_depth(), 1);
+        assert_eq!(book.ask_depth(), 0);
+
+        // 删除不存在的档位不影响盘口
+        book.apply(&inc(3, false, &[(98.0, 0.0)], &[]));
+        assert_eq!(book.bid_depth(), 1);
+    }
+
+    #[test]
+    fn non_zero_amount_replaces_the_level() {
+        let mut book = OrderBook::new();
+        book.apply(&inc(1, true, &[(100.0, 1.0)], &[(101.0, 1.0)]));
+        book.apply(&inc(2, false, &[(100.0, 5.0)], &[(101.0, 0.5)]));
+        assert_eq!(book.best_bid(), Some(PriceLevel { price: 100.0, amount: 5.0 }));
+        assert_eq!(book.best_ask(), Some(PriceLevel { price: 101.0, amount: 0.5 }));
+        assert_eq!(book.spread(), Some(1.0));
+        assert_eq!(book.mid(), Some(100.5));
+        assert!(!book.is_crossed());
+    }
+
+    #[test]
+    fn state_at_applies_incs_up_to_and_including_ts() {
+        let mut start = OrderBook::new();
+        start.apply(&inc(0, true, &[(100.0, 1.0)], &[(101.0, 1.0)]));
+        let info = symbol(vec![
+            inc(10, false, &[(100.0, 2.0)], &[]),
+            inc(20, false, &[(100.0, 3.0)], &[]),
+            inc(30, false, &[(100.0, 4.0)], &[]),
+        ]);
+
+        assert_eq!(start.state_at(&info, 5).best_bid().unwrap().amount, 1.0);
+        assert_eq!(start.state_at(&info, 20).best_bid().unwrap().amount, 3.0);
+        assert_eq!(start.state_at(&info, 20).last_ts(), Some(20));
+        assert_eq!(start.state_at(&info, 29).best_bid().unwrap().amount, 3.0);
+        assert_eq!(start.state_at(&info, 30).best_bid().unwrap().amount, 4.0);
+        // 不修改原盘口
+        assert_eq!(start.best_bid().unwrap().amount, 1.0);
+    }
+
+    #[test]
+    fn top_levels_are_ordered_from_the_touch() {
+        let mut book = OrderBook::new();
+        book.apply(&inc(
+            1,
+            true,
+            &[(98.0, 1.0), (100.0, 1.0), (99.0, 1.0)],
+            &[(103.0, 1.0), (101.0, 1.0), (102.0, 1.0)],
+        ));
+        let bids: Vec<f64> = book.top_bids(2).iter().map(|l| l.price).collect();
+        let asks: Vec<f64> = book.top_asks(2).iter().map(|l| l.price).collect();
+        assert_eq!(bids, [100.0, 99.0]);
+        assert_eq!(asks, [101.0, 102.0]);
+        assert_eq!(book.top_bids(10).len(), 3);
+    }
+
+    #[test]
+    fn crossed_book_is_detected() {
+        let mut book = OrderBook::new();
+        book.apply(&inc(1, true, &[(101.0, 1.0)], &[(101.0, 1.0)]));
+        assert!(book.is_crossed());
+    }
+
+    #[test]
+    fn order_books_track_period_continuity() {
+        let msg = |period| PeriodMessage {
+            period,
+            ts: 0,
+            post_ts: 0,
+            poster_id: String::new(),
+            symbol_infos: vec![symbol(vec![inc(period, true, &[(100.0, 1.0)], &[])])],
+        };
+        let mut books = OrderBooks::new();
+        assert!(books.apply_period(&msg(1)));
+        assert!(books.apply_period(&msg(2)));
+        assert!(!books.apply_period(&msg(4)));
+        assert_eq!(books.last_period(), Some(4));
+        assert!(books.get("BTC").unwrap().has_snapshot());
+    }
+}
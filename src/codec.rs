@@ -0,0 +1,392 @@
+// 消息编码格式: capnp, capnp packed 和 protobuf, 以及收到数据时的格式识别
+//
+// 数据可以带 4 字节的显式头部 "MKP" + 格式字节, 头部之后为可能压缩过的消息;
+// 没有头部时先按魔数解压, 再按内容猜测格式, 见 `PeriodMessage::decode_any`。
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use capnp::message::ReaderOptions;
+use log::warn;
+use capnp::serialize_packed;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::compression::{decompress_auto, Compression};
+use crate::config::DecodeConfig;
+use crate::message::PeriodMessage;
+use crate::view::{period_root, DecodeError};
+use crate::proto::message_old;
+
+pub const HEADER_MAGIC: [u8; 3] = *b"MKP";
+pub const HEADER_LEN: usize = 4;
+
+// capnp 单条消息的段数上限, 与 capnp 库的默认限制一致
+const MAX_SEGMENTS: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecKind {
+    Capnp,
+    CapnpPacked,
+    Protobuf,
+}
+
+impl CodecKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CodecKind::Capnp => "capnp",
+            CodecKind::CapnpPacked => "capnp_packed",
+            CodecKind::Protobuf => "protobuf",
+        }
+    }
+
+    /// 显式头部中的格式字节
+    pub fn header_byte(&self) -> u8 {
+        match self {
+            CodecKind::Capnp => 1,
+            CodecKind::CapnpPacked => 2,
+            CodecKind::Protobuf => 3,
+        }
+    }
+
+    pub fn from_header_byte(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(CodecKind::Capnp),
+            2 => Some(CodecKind::CapnpPacked),
+            3 => Some(CodecKind::Protobuf),
+            _ => None,
+        }
+    }
+
+    pub fn codec(&self) -> &'static dyn Codec {
+        match self {
+            CodecKind::Capnp => &CapnpCodec,
+            CodecKind::CapnpPacked => &CapnpPackedCodec,
+            CodecKind::Protobuf => &ProtobufCodec,
+        }
+    }
+}
+
+/// 未压缩的 `PeriodMessage` 编解码, 压缩见 [`Compression`](crate::Compression)
+pub trait Codec: Send + Sync {
+    fn kind(&self) -> CodecKind;
+    /// capnp 格式超过 `options` 的限制时返回 [`DecodeError::TooLarge`], protobuf 不受限制
+    fn decode(&self, data: &[u8], options: ReaderOptions) -> Result<PeriodMessage>;
+    fn encode(&self, msg: &PeriodMessage) -> Result<Vec<u8>>;
+}
+
+pub struct CapnpCodec;
+
+impl Codec for CapnpCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Capnp
+    }
+
+    fn decode(&self, data: &[u8], options: ReaderOptions) -> Result<PeriodMessage> {
+        PeriodMessage::from_capnp_with_options(data, false, options)
+    }
+
+    fn encode(&self, msg: &PeriodMessage) -> Result<Vec<u8>> {
+        msg.to_capnp(false)
+    }
+}
+
+pub struct CapnpPackedCodec;
+
+impl Codec for CapnpPackedCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::CapnpPacked
+    }
+
+    fn decode(&self, mut data: &[u8], options: ReaderOptions) -> Result<PeriodMessage> {
+        serialize_packed::read_message(&mut data, options)
+            .map_err(anyhow::Error::from)
+            .and_then(|message| PeriodMessage::from_capnp_reader(period_root(&message)?))
+            .map_err(DecodeError::classify)
+    }
+
+    fn encode(&self, msg: &PeriodMessage) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        serialize_packed::write_message(&mut buffer, &msg.to_capnp_builder())?;
+        Ok(buffer)
+    }
+}
+
+pub struct ProtobufCodec;
+
+impl Codec for ProtobufCodec {
+    fn kind(&self) -> CodecKind {
+        CodecKind::Protobuf
+    }
+
+    fn decode(&self, data: &[u8], _options: ReaderOptions) -> Result<PeriodMessage> {
+        PeriodMessage::from_protobuf(data, false)
+    }
+
+    fn encode(&self, msg: &PeriodMessage) -> Result<Vec<u8>> {
+        msg.to_protobuf(false)
+    }
+}
+
+/// 在已压缩或未压缩的消息前加上显式头部
+pub fn with_header(kind: CodecKind, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + payload.len());
+    out.extend_from_slice(&HEADER_MAGIC);
+    out.push(kind.header_byte());
+    out.extend_from_slice(payload);
+    out
+}
+
+/// 有显式头部时返回格式和头部之后的数据
+pub fn split_header(data: &[u8]) -> Option<(CodecKind, &[u8])> {
+    if data.len() < HEADER_LEN || data[..3] != HEADER_MAGIC {
+        return None;
+    }
+    Some((CodecKind::from_header_byte(data[3])?, &data[HEADER_LEN..]))
+}
+
+/// 未压缩数据是否符合 capnp 标准序列化的分段格式: 段表声明的总长度与数据长度一致
+pub fn looks_like_capnp(data: &[u8]) -> bool {
+    if data.len() < 8 {
+        return false;
+    }
+    let segments = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize + 1;
+    if segments > MAX_SEGMENTS {
+        return false;
+    }
+    // 段表按 8 字节对齐
+    let table_len = (4 + 4 * segments).div_ceil(8) * 8;
+    if data.len() < table_len {
+        return false;
+    }
+    let words: usize = (0..segments)
+        .map(|i| {
+            let at = 4 + 4 * i;
+            u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize
+        })
+        .sum();
+    table_len + words * 8 == data.len()
+}
+
+// protobuf PeriodMessage 各字段的 key 字节: period, ts, post_ts 为 varint, poster_id
+// 和 symbol_infos 为 length-delimited
+fn starts_like_protobuf(data: &[u8]) -> bool {
+    matches!(data.first(), Some(0x08 | 0x10 | 0x18 | 0x22 | 0x2a))
+}
+
+/// 猜测未压缩数据的格式
+///
+/// 段表与长度吻合时为 capnp; 否则首字节是 PeriodMessage 某个字段的 protobuf key
+/// 且能完整解码时为 protobuf; 都不是时按 capnp packed 处理。
+pub fn detect(data: &[u8]) -> CodecKind {
+    if looks_like_capnp(data) {
+        CodecKind::Capnp
+    } else if starts_like_protobuf(data) && message_old::PeriodMessage::decode(data).is_ok() {
+        CodecKind::Protobuf
+    } else {
+        CodecKind::CapnpPacked
+    }
+}
+
+impl PeriodMessage {
+    /// 解码任意格式和压缩方式的消息, 返回消息和识别出的格式
+    pub fn decode_any(data: &[u8]) -> Result<(Self, CodecKind)> {
+        Self::decode_any_with_options(data, ReaderOptions::new())
+    }
+
+    /// 同 [`decode_any`](Self::decode_any), 超过 `options` 的限制时返回 [`DecodeError::TooLarge`]
+    pub fn decode_any_with_options(data: &[u8], options: ReaderOptions) -> Result<(Self, CodecKind)> {
+        let (kind, payload) = match split_header(data) {
+            Some((kind, payload)) => (Some(kind), payload),
+            None => (None, data),
+        };
+        let payload = decompress_auto(payload)?;
+        let kind = kind.unwrap_or_else(|| detect(&payload));
+        let msg = kind
+            .codec()
+            .decode(&payload, options)
+            .with_context(|| format!("按 {} 解码失败", kind.as_str()))?;
+        Ok((msg, kind))
+    }
+}
+
+/// [`normalize_to_capnp`] 的结果
+pub struct Normalized {
+    // 发布使用的 capnp, 收到的就是 capnp 时为去掉头部的原始数据, 可能已压缩
+    pub raw: Bytes,
+    // 解压后的 capnp, 供解析头部和条数使用, 避免再解压一次
+    pub plain: Bytes,
+}
+
+/// 转换为发布管道使用的 capnp; 已经是 capnp 时原样返回, 不做解码
+///
+/// 整个过程只解压一次, 其他格式按 `cfg` 的限制解码后转码, 按默认压缩格式压缩。
+/// 超过限制且开启 `oversize_fallback` 时不限遍历量重新解码, 转码后的 capnp
+/// 由发布路径按超限 period 处理; 未开启时返回 [`DecodeError::TooLarge`]。
+pub fn normalize_to_capnp(data: Bytes, cfg: &DecodeConfig) -> Result<Normalized> {
+    let (kind, payload) = match split_header(&data) {
+        Some((kind, _)) => (Some(kind), data.slice(HEADER_LEN..)),
+        None => (None, data),
+    };
+    let plain = match decompress_auto(&payload)? {
+        Cow::Borrowed(_) => payload.clone(),
+        Cow::Owned(plain) => Bytes::from(plain),
+    };
+    let kind = kind.unwrap_or_else(|| detect(&plain));
+    if kind == CodecKind::Capnp {
+        return Ok(Normalized { raw: payload, plain });
+    }
+    let codec = kind.codec();
+    let msg = match codec.decode(&plain, cfg.reader_options()) {
+        Err(e) if cfg.oversize_fallback && DecodeError::is_too_large(&e) => {
+            warn!("按 {} 解码 {}, 不限遍历量重新解码", kind.as_str(), e);
+            let mut options = cfg.reader_options();
+            options.traversal_limit_in_words(None);
+            codec.decode(&plain, options)
+        }
+        result => result,
+    }
+    .with_context(|| format!("按 {} 解码失败", kind.as_str()))?;
+    let plain = Bytes::from(CapnpCodec.encode(&msg)?);
+    let raw = Bytes::from(Compression::default().compress(&plain)?);
+    Ok(Normalized { raw, plain })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{IncrementOrderBookInfo, PriceLevel, Side, SymbolInfo, TradeInfo};
+
+    const KINDS: [CodecKind; 3] = [CodecKind::Capnp, CodecKind::CapnpPacked, CodecKind::Protobuf];
+    const COMPRESSIONS: [Compression; 3] = [Compression::None, Compression::Zlib(6), Compression::Zstd(3)];
+
+    fn message() -> PeriodMessage {
+        PeriodMessage {
+            period: 42,
+            ts: 126_000,
+            post_ts: 126_005,
+            poster_id: "poster-1".to_string(),
+            symbol_infos: vec![SymbolInfo {
+                symbol: "BTC".to_string(),
+                trades: vec![TradeInfo {
+                    timestamp: 125_000,
+                    side: Side::Sell,
+                    price: 100.5,
+                    amount: 0.25,
+                }],
+                incs: vec![IncrementOrderBookInfo {
+                    timestamp: 125_500,
+                    is_snapshot: true,
+                    bids: vec![PriceLevel { price: 100.0, amount: 1.0 }],
+                    asks: vec![PriceLevel { price: 101.0, amount: 2.0 }],
+                }],
+            }],
+        }
+    }
+
+    fn encode(kind: CodecKind, compression: Compression, header: bool) -> Vec<u8> {
+        let plain = kind.codec().encode(&message()).unwrap();
+        let payload = compression.compress(&plain).unwrap();
+        if header {
+            with_header(kind, &payload)
+        } else {
+            payload
+        }
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        for kind in KINDS {
+            let codec = kind.codec();
+            assert_eq!(codec.kind(), kind);
+            let data = codec.encode(&message()).unwrap();
+            assert_eq!(codec.decode(&data, ReaderOptions::new()).unwrap(), message(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn header_byte_round_trips() {
+        for kind in KINDS {
+            assert_eq!(CodecKind::from_header_byte(kind.header_byte()), Some(kind));
+        }
+        assert_eq!(CodecKind::from_header_byte(0), None);
+        assert!(split_header(b"MKP\x09rest").is_none());
+        assert!(split_header(b"MK").is_none());
+    }
+
+    #[test]
+    fn decode_any_with_and_without_header() {
+        for kind in KINDS {
+            for compression in COMPRESSIONS {
+                for header in [true, false] {
+                    let data = encode(kind, compression, header);
+                    let (msg, detected) = PeriodMessage::decode_any(&data)
+                        .unwrap_or_else(|e| panic!("{:?} {:?} header {}: {:#}", kind, compression, header, e));
+                    assert_eq!(msg, message());
+                    assert_eq!(detected, kind, "{:?} header {}", compression, header);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn detect_guesses_uncompressed_formats() {
+        for kind in KINDS {
+            assert_eq!(detect(&kind.codec().encode(&message()).unwrap()), kind);
+        }
+        assert!(looks_like_capnp(&CapnpCodec.encode(&message()).unwrap()));
+        assert!(!looks_like_capnp(&ProtobufCodec.encode(&message()).unwrap()));
+    }
+
+    #[test]
+    fn unknown_payload_is_rejected() {
+        let payloads: [&[u8]; 5] = [
+            // 根指针为空的 capnp packed, 不检查时解码出全为默认值的 period
+            &[0x10, 0x01, 0x00, 0x00],
+            b"{\"period\": 42, \"ts\": 126000}",
+            b"not a period message at all",
+            &[0xff; 64],
+            &[0x08, 0x2a, 0xff, 0xff, 0xff],
+        ];
+        for data in payloads {
+            assert!(PeriodMessage::decode_any(data).is_err(), "{:?}", data);
+            assert!(normalize_to_capnp(Bytes::copy_from_slice(data), &DecodeConfig::default()).is_err(), "{:?}", data);
+        }
+
+        // 根指针为空的标准 capnp 在转换时原样通过, 由之后读取头部时拒绝
+        let null_root = [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(PeriodMessage::decode_any(&null_root).is_err());
+        let normalized = normalize_to_capnp(Bytes::copy_from_slice(&null_root), &DecodeConfig::default()).unwrap();
+        assert!(crate::view::summarize(&normalized.plain, false, &DecodeConfig::default(), false).is_err());
+        // 头部声明的格式与内容不符时按头部解码, 同样失败
+        let data = with_header(CodecKind::Protobuf, &CapnpCodec.encode(&message()).unwrap());
+        assert!(PeriodMessage::decode_any(&data).is_err());
+    }
+
+    #[test]
+    fn normalize_passes_capnp_through() {
+        for compression in COMPRESSIONS {
+            for header in [true, false] {
+                let data = encode(CodecKind::Capnp, compression, header);
+                let payload = if header { &data[HEADER_LEN..] } else { &data[..] };
+                let normalized = normalize_to_capnp(Bytes::from(data.clone()), &DecodeConfig::default()).unwrap();
+                assert_eq!(normalized.raw, payload);
+                assert_eq!(normalized.plain, CapnpCodec.encode(&message()).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn normalize_transcodes_other_formats() {
+        for kind in [CodecKind::CapnpPacked, CodecKind::Protobuf] {
+            for compression in COMPRESSIONS {
+                for header in [true, false] {
+                    let data = encode(kind, compression, header);
+                    let normalized = normalize_to_capnp(Bytes::from(data), &DecodeConfig::default()).unwrap();
+                    assert_eq!(CapnpCodec.decode(&normalized.plain, ReaderOptions::new()).unwrap(), message());
+                    assert_eq!(PeriodMessage::from_capnp(&normalized.raw, true).unwrap(), message());
+                }
+            }
+        }
+    }
+}
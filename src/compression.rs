@@ -0,0 +1,267 @@
+// 压缩格式: 按魔数识别收到的数据, 发布时可按输出选择重新压缩的格式
+use anyhow::Result;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+// flate2::Compression::default() 的级别
+pub const DEFAULT_ZLIB_LEVEL: u32 = 6;
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+// LZ4 frame 格式的魔数 0x184D2204, 小端
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Zlib(u32),
+    Gzip(u32),
+    Zstd(i32),
+    // LZ4 frame 格式, 不支持级别
+    Lz4,
+}
+
+impl Default for Compression {
+    /// 与旧的 `use_compression: true` 相同
+    fn default() -> Self {
+        Compression::Zlib(DEFAULT_ZLIB_LEVEL)
+    }
+}
+
+impl Compression {
+    /// 按魔数识别压缩格式, 识别不出时视为未压缩; 返回的级别为默认值
+    ///
+    /// zlib 头的第一个字节为 0x78 时还要满足头部两字节按大端为 31 的倍数。
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd(DEFAULT_ZSTD_LEVEL)
+        } else if data.starts_with(&LZ4_MAGIC) {
+            Compression::Lz4
+        } else if data.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip(DEFAULT_ZLIB_LEVEL)
+        } else if data.len() >= 2 && data[0] == 0x78 && u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31) {
+            Compression::Zlib(DEFAULT_ZLIB_LEVEL)
+        } else {
+            Compression::None
+        }
+    }
+
+    pub fn kind(&self) -> CompressionKind {
+        match self {
+            Compression::None => CompressionKind::None,
+            Compression::Zlib(_) => CompressionKind::Zlib,
+            Compression::Gzip(_) => CompressionKind::Gzip,
+            Compression::Zstd(_) => CompressionKind::Zstd,
+            Compression::Lz4 => CompressionKind::Lz4,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.kind().as_str()
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match *self {
+            Compression::None => data.to_vec(),
+            Compression::Zlib(level) => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Gzip(level) => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Zstd(level) => zstd::encode_all(data, level)?,
+            Compression::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+        })
+    }
+
+    /// 按本格式解压, 级别不影响解压
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Compression::None => out.extend_from_slice(data),
+            Compression::Zlib(_) => {
+                ZlibDecoder::new(data).read_to_end(&mut out)?;
+            }
+            Compression::Gzip(_) => {
+                GzDecoder::new(data).read_to_end(&mut out)?;
+            }
+            Compression::Zstd(_) => out = zstd::decode_all(data)?,
+            Compression::Lz4 => {
+                lz4_flex::frame::FrameDecoder::new(data).read_to_end(&mut out)?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// 识别格式并解压, 未压缩的数据直接借用
+pub fn decompress_auto(data: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match Compression::detect(data) {
+        Compression::None => Ok(Cow::Borrowed(data)),
+        compression => Ok(Cow::Owned(compression.decompress(data)?)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionKind {
+    None,
+    Zlib,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionKind::None => "none",
+            CompressionKind::Zlib => "zlib",
+            CompressionKind::Gzip => "gzip",
+            CompressionKind::Zstd => "zstd",
+            CompressionKind::Lz4 => "lz4",
+        }
+    }
+}
+
+/// 配置文件中的压缩格式, 如 `{ codec: zstd, level: 3 }`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct CompressionConfig {
+    pub codec: CompressionKind,
+    // 不填时使用各格式的默认级别; zlib 和 gzip 为 0-9, zstd 为 1-22, lz4 忽略
+    #[serde(default)]
+    pub level: Option<i32>,
+}
+
+impl CompressionConfig {
+    pub fn validate(&self) -> Result<()> {
+        let Some(level) = self.level else {
+            return Ok(());
+        };
+        let valid = match self.codec {
+            CompressionKind::Zlib | CompressionKind::Gzip => (0..=9).contains(&level),
+            CompressionKind::Zstd => (1..=22).contains(&level),
+            CompressionKind::None | CompressionKind::Lz4 => true,
+        };
+        if !valid {
+            anyhow::bail!("invalid {} compression level: {}", self.codec.as_str(), level);
+        }
+        Ok(())
+    }
+
+    pub fn compression(&self) -> Compression {
+        match self.codec {
+            CompressionKind::None => Compression::None,
+            CompressionKind::Zlib => Compression::Zlib(self.level.map_or(DEFAULT_ZLIB_LEVEL, |l| l as u32)),
+            CompressionKind::Gzip => Compression::Gzip(self.level.map_or(DEFAULT_ZLIB_LEVEL, |l| l as u32)),
+            CompressionKind::Zstd => Compression::Zstd(self.level.unwrap_or(DEFAULT_ZSTD_LEVEL)),
+            CompressionKind::Lz4 => Compression::Lz4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{PeriodMessage, Side, SymbolInfo, TradeInfo};
+
+    const ALL: [Compression; 5] = [
+        Compression::None,
+        Compression::Zlib(DEFAULT_ZLIB_LEVEL),
+        Compression::Gzip(DEFAULT_ZLIB_LEVEL),
+        Compression::Zstd(DEFAULT_ZSTD_LEVEL),
+        Compression::Lz4,
+    ];
+
+    fn capnp_message() -> Vec<u8> {
+        PeriodMessage {
+            period: 1,
+            ts: 3_000,
+            post_ts: 3_001,
+            poster_id: "p".to_string(),
+            symbol_infos: vec![SymbolInfo {
+                symbol: "BTC".to_string(),
+                trades: vec![TradeInfo {
+                    timestamp: 2_000,
+                    side: Side::Buy,
+                    price: 100.0,
+                    amount: 1.0,
+                }],
+                incs: vec![],
+            }],
+        }
+        .to_capnp(false)
+        .unwrap()
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        let data = capnp_message();
+        for compression in ALL {
+            let compressed = compression.compress(&data).unwrap();
+            assert_eq!(compression.decompress(&compressed).unwrap(), data, "{:?}", compression);
+            assert_eq!(decompress_auto(&compressed).unwrap(), data.as_slice(), "{:?}", compression);
+        }
+    }
+
+    #[test]
+    fn levels_do_not_affect_decompression() {
+        let data = capnp_message();
+        let compressed = Compression::Zstd(19).compress(&data).unwrap();
+        assert_eq!(Compression::Zstd(1).decompress(&compressed).unwrap(), data);
+        let compressed = Compression::Gzip(9).compress(&data).unwrap();
+        assert_eq!(Compression::Gzip(0).decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn detects_each_format_by_magic() {
+        let data = capnp_message();
+        for compression in ALL {
+            let compressed = compression.compress(&data).unwrap();
+            assert_eq!(Compression::detect(&compressed).kind(), compression.kind());
+        }
+        // 每种 zlib 级别的头部都能识别
+        for level in 0..=9 {
+            let compressed = Compression::Zlib(level).compress(&data).unwrap();
+            assert_eq!(Compression::detect(&compressed).kind(), CompressionKind::Zlib, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn uncompressed_capnp_is_not_detected_as_compressed() {
+        let data = capnp_message();
+        assert_eq!(Compression::detect(&data), Compression::None);
+        assert!(matches!(decompress_auto(&data).unwrap(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn zlib_needs_a_valid_header_checksum() {
+        assert_eq!(Compression::detect(&[0x78, 0x9c]).kind(), CompressionKind::Zlib);
+        assert_eq!(Compression::detect(&[0x78, 0x00]), Compression::None);
+        assert_eq!(Compression::detect(&[0x78]), Compression::None);
+        assert_eq!(Compression::detect(&[]), Compression::None);
+    }
+
+    #[test]
+    fn config_levels_are_checked_per_codec() {
+        let config = |codec, level| CompressionConfig { codec, level };
+        assert!(config(CompressionKind::Zlib, Some(9)).validate().is_ok());
+        assert!(config(CompressionKind::Zlib, Some(10)).validate().is_err());
+        assert!(config(CompressionKind::Zstd, Some(0)).validate().is_err());
+        assert!(config(CompressionKind::Lz4, Some(100)).validate().is_ok());
+        assert_eq!(config(CompressionKind::Zstd, None).compression(), Compression::Zstd(DEFAULT_ZSTD_LEVEL));
+        assert_eq!(config(CompressionKind::Gzip, Some(1)).compression(), Compression::Gzip(1));
+    }
+}
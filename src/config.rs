@@ -1,7 +1,13 @@
-use serde::Deserialize;
+use capnp::message::ReaderOptions;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 use std::fs;
 
-#[derive(Debug, Deserialize, PartialEq)]
+use crate::compression::{Compression, CompressionConfig};
+use crate::stats::BarInterval;
+use crate::validate::{Severity, DEFAULT_PERIOD_MS};
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub enum Mode {
     #[serde(rename = "FromStart")]
     FromStart,
@@ -13,20 +19,528 @@ pub enum Mode {
     Error,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisTopology {
+    #[default]
+    Standalone,
+    Sentinel,
+    Cluster,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // PEM 格式的 CA 证书, 不填时使用系统信任的根证书
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    // 双向认证时的客户端证书和私钥
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    #[serde(default)]
+    pub client_key: Option<String>,
+    // 跳过证书校验, 仅用于测试
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RedisPubberConfig {
     pub host: String,
     pub port: String,
     pub username: String,
     pub password: String,
+    #[serde(default)]
+    pub topology: RedisTopology,
+    // sentinel 或 cluster 的节点列表, 形如 "host:port"
+    #[serde(default)]
+    pub nodes: Vec<String>,
+    #[serde(default)]
+    pub sentinel_master: Option<String>,
+    #[serde(default)]
+    pub sentinel_password: Option<String>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
     pub max_stream_size: usize,
     pub mode: Mode,
+    // FromStart 模式下用于补数据的本地归档目录
+    #[serde(default = "default_archive_dir")]
+    pub archive_dir: String,
+    // 为 true 时转码为 protobuf 后发布, 否则直接发布原始 capnp
+    #[serde(default)]
+    pub publish_as_protobuf: bool,
+    // 发布时重新压缩的格式; 不填时 capnp 原样发布, protobuf 使用默认级别的 zlib
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    // 期望的发布脚本 sha1; 填写时与编译进二进制的 push_msg.lua 不一致则拒绝启动
+    #[serde(default)]
+    pub script_sha: Option<String>,
+}
+
+fn default_archive_dir() -> String {
+    "./period_archive".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ZmqProxyConfig {
+    #[serde(default = "default_ipc_path")]
+    pub ipc_path: String,
+    #[serde(default)]
+    pub primary_addr: Option<String>,
+    #[serde(default)]
+    pub secondary_addr: Option<String>,
+    #[serde(default = "default_hwm")]
+    pub hwm: i32,
+    // 显式指定的接入地址, 支持 ipc:// 和 tcp://, 不填时使用 ipc_path
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    // 订阅的主题前缀, 空字符串表示订阅全部
+    #[serde(default = "default_topics")]
+    pub topics: Vec<String>,
+    // 接收器到发布管道的队列长度及队列满时的处理策略
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+    // 为 true 时同时订阅 primary_addr 和 secondary_addr 两路行情, 按 period 择优去重;
+    // 两路按 poster_id 区分, 处理器的 poster_id 必须不同
+    #[serde(default)]
+    pub dual_feed: bool,
+    #[serde(default)]
+    pub arbiter: ArbiterConfig,
+    // 为 true 时识别收到的消息格式 (capnp, capnp packed, protobuf), 非 capnp 的转为 capnp 后发布;
+    // 为 false 时按压缩 capnp 处理, 不做额外检查
+    #[serde(default)]
+    pub detect_codec: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ArbiterConfig {
+    // 收到某个 period 的第一份后最多等待其他行情源的时间
+    #[serde(default = "default_arbiter_window_ms")]
+    pub window_ms: u64,
+    // info_count 相同时优先采用的 poster_id, 越靠前优先级越高
+    #[serde(default)]
+    pub poster_priority: Vec<String>,
+}
+
+fn default_arbiter_window_ms() -> u64 {
+    1000
+}
+
+impl Default for ArbiterConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: default_arbiter_window_ms(),
+            poster_priority: Vec::new(),
+        }
+    }
+}
+
+// 发布跟不上接收时的处理策略
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    // 等待发布腾出空位, 期间不从 socket 收取。SUB socket 不会向上游施加背压,
+    // 消息堆积超过 ZMQ 接收水位线后由 ZMQ 静默丢弃, 不计数也不打印;
+    // 这部分丢失只能通过 sequencer 的缺口检测发现。多个交易所共用 socket 时,
+    // 一个交易所的队列满会阻塞所有交易所的接收
+    #[default]
+    Block,
+    // 丢弃队列中最旧的一条
+    DropOldest,
+    // 丢弃新收到的一条
+    DropNewest,
+}
+
+fn default_is_primary() -> bool {
+    true
+}
+
+fn default_ipc_path() -> String {
+    "/tmp/mkt_archive.ipc".to_string()
+}
+
+fn default_hwm() -> i32 {
+    100
+}
+
+fn default_topics() -> Vec<String> {
+    vec![String::new()]
+}
+
+fn default_queue_size() -> usize {
+    64
+}
+
+impl Default for ZmqProxyConfig {
+    fn default() -> Self {
+        Self {
+            ipc_path: default_ipc_path(),
+            primary_addr: None,
+            secondary_addr: None,
+            hwm: default_hwm(),
+            endpoints: Vec::new(),
+            topics: default_topics(),
+            queue_size: default_queue_size(),
+            overflow: OverflowPolicy::default(),
+            dual_feed: false,
+            arbiter: ArbiterConfig::default(),
+            detect_codec: false,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+impl ZmqProxyConfig {
+    /// 接收器需要连接的全部地址, 不带协议前缀的地址按 tcp 处理
+    ///
+    /// 开启 `dual_feed` 时连接 primary_addr 和 secondary_addr。
+    pub fn endpoints(&self) -> Vec<String> {
+        let addrs: Vec<&String> = if self.dual_feed {
+            self.primary_addr.iter().chain(&self.secondary_addr).collect()
+        } else if self.endpoints.is_empty() {
+            return vec![format!("ipc://{}", self.ipc_path)];
+        } else {
+            self.endpoints.iter().collect()
+        };
+        addrs
+            .into_iter()
+            .map(|addr| {
+                if addr.contains("://") {
+                    addr.clone()
+                } else {
+                    format!("tcp://{}", addr)
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct HaConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // 租约时长, 主失联超过该时长后备接管
+    #[serde(default = "default_lease_ttl_ms")]
+    pub lease_ttl_ms: u64,
+    #[serde(default = "default_renew_interval_ms")]
+    pub renew_interval_ms: u64,
+}
+
+fn default_lease_ttl_ms() -> u64 {
+    10_000
+}
+
+fn default_renew_interval_ms() -> u64 {
+    3_000
+}
+
+impl Default for HaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lease_ttl_ms: default_lease_ttl_ms(),
+            renew_interval_ms: default_renew_interval_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct WatchdogConfig {
+    #[serde(default = "default_watchdog_enabled")]
+    pub enabled: bool,
+    // 上游 period 的产出间隔
+    #[serde(default = "default_period_interval_secs")]
+    pub period_interval_secs: u64,
+    // 超过这么多个间隔没有收到新的 period 时告警
+    #[serde(default = "default_silence_intervals")]
+    pub silence_intervals: u32,
+    // 健康检查 HTTP 地址, 如 "0.0.0.0:9100", 不填时不启动
+    #[serde(default)]
+    pub health_addr: Option<String>,
+    // 告警期间每个间隔向心跳 Stream 写一条 HEARTBEAT 标记, 仅主实例写入
+    #[serde(default)]
+    pub heartbeat: bool,
+}
+
+fn default_watchdog_enabled() -> bool {
+    true
+}
+
+fn default_period_interval_secs() -> u64 {
+    3
+}
+
+fn default_silence_intervals() -> u32 {
+    3
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_watchdog_enabled(),
+            period_interval_secs: default_period_interval_secs(),
+            silence_intervals: default_silence_intervals(),
+            health_addr: None,
+            heartbeat: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct SequencerConfig {
+    // 为 true 时按 period 顺序发布, 缺失的 period 写入 GAP 条目
+    #[serde(default)]
+    pub enabled: bool,
+    // 最多缓存的乱序 period 跨度, 超出时不再等待缺失的 period
+    #[serde(default = "default_sequencer_window")]
+    pub window: usize,
+    // 等待缺失 period 的最长时间
+    #[serde(default = "default_gap_timeout_ms")]
+    pub gap_timeout_ms: u64,
+    // 一次缺失超过这么多个 period 时不再逐个写 GAP 条目, 只写一条 GAP_RANGE 范围标记
+    #[serde(default = "default_max_gap_entries")]
+    pub max_gap_entries: usize,
+}
+
+fn default_sequencer_window() -> usize {
+    8
+}
+
+fn default_gap_timeout_ms() -> u64 {
+    9_000
+}
+
+fn default_max_gap_entries() -> usize {
+    16
+}
+
+impl Default for SequencerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: default_sequencer_window(),
+            gap_timeout_ms: default_gap_timeout_ms(),
+            max_gap_entries: default_max_gap_entries(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct HoldBackConfig {
+    // 为 true 时每个 period 缓存一段时间, 只发布 info_count 最大的最终版本
+    #[serde(default)]
+    pub enabled: bool,
+    // 首次收到某个 period 后等待的时间
+    #[serde(default = "default_hold_back_delay_ms")]
+    pub delay_ms: u64,
+}
+
+fn default_hold_back_delay_ms() -> u64 {
+    1_000
+}
+
+impl Default for HoldBackConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay_ms: default_hold_back_delay_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ValidationConfig {
+    // 为 true 时发布前完整解码每个 period 并检查数据质量, 按 symbol 统计违规
+    #[serde(default)]
+    pub enabled: bool,
+    // period 的时间窗口长度, trade 和 inc 的时间戳应落在 [ts - period_ms, ts) 内
+    #[serde(default = "default_validation_period_ms")]
+    pub period_ms: i64,
+    // 有违规达到该级别 (warning 或 error) 的 period 不发布, 不填时只统计不拦截
+    #[serde(default)]
+    pub reject_severity: Option<Severity>,
+}
+
+fn default_validation_period_ms() -> i64 {
+    DEFAULT_PERIOD_MS
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            period_ms: default_validation_period_ms(),
+            reject_severity: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct StatsConfig {
+    // 为 true 时按 period 计算各 symbol 的统计, 写入 "{stream}:stats" Stream
+    #[serde(default)]
+    pub enabled: bool,
+    // 是否写入每个 period 的统计, 为 false 时只写 K 线
+    #[serde(default = "default_publish_periods")]
+    pub publish_periods: bool,
+    // 汇总的 K 线周期
+    #[serde(default = "default_bar_intervals")]
+    pub bars: Vec<BarInterval>,
+    // 统计 Stream 的最大长度 (近似裁剪)
+    #[serde(default = "default_stats_max_stream_size")]
+    pub max_stream_size: usize,
+}
+
+fn default_publish_periods() -> bool {
+    true
+}
+
+fn default_bar_intervals() -> Vec<BarInterval> {
+    vec![BarInterval::M1, BarInterval::M5, BarInterval::H1]
+}
+
+fn default_stats_max_stream_size() -> usize {
+    10_000
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            publish_periods: default_publish_periods(),
+            bars: default_bar_intervals(),
+            max_stream_size: default_stats_max_stream_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct DecodeConfig {
+    // capnp 解码最多遍历的字数 (8 字节), null 表示不限制; 默认与 capnp 库相同, 为 8M 字
+    #[serde(default = "default_traversal_limit_words")]
+    pub traversal_limit_words: Option<usize>,
+    #[serde(default = "default_nesting_limit")]
+    pub nesting_limit: i32,
+    // 超过限制时不丢弃: 只统计条数, 原样发布收到的数据, 跳过转码, 质量检查和统计
+    #[serde(default = "default_oversize_fallback")]
+    pub oversize_fallback: bool,
+}
+
+fn default_traversal_limit_words() -> Option<usize> {
+    Some(8 * 1024 * 1024)
+}
+
+fn default_nesting_limit() -> i32 {
+    64
+}
+
+fn default_oversize_fallback() -> bool {
+    true
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        Self {
+            traversal_limit_words: default_traversal_limit_words(),
+            nesting_limit: default_nesting_limit(),
+            oversize_fallback: default_oversize_fallback(),
+        }
+    }
+}
+
+impl DecodeConfig {
+    pub fn reader_options(&self) -> ReaderOptions {
+        let mut options = ReaderOptions::new();
+        options
+            .traversal_limit_in_words(self.traversal_limit_words)
+            .nesting_limit(self.nesting_limit);
+        options
+    }
+}
+
+// msg_content 的编码格式, 写入 Stream 的 codec 字段
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadCodec {
+    Capnp,
+    Protobuf,
+}
+
+impl PayloadCodec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadCodec::Capnp => "capnp",
+            PayloadCodec::Protobuf => "protobuf",
+        }
+    }
+}
+
+// yaml 中 exchanges 列表的一项, 未填写的字段继承顶层配置
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ExchangeEntry {
+    pub name: String,
+    #[serde(default)]
+    pub stream_key: Option<String>,
+    // 多帧消息 [topic, payload] 中路由到该交易所的 topic, 默认为交易所名
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub zmq: Option<ZmqProxyConfig>,
+    #[serde(default)]
+    pub max_stream_size: Option<usize>,
+    #[serde(default)]
+    pub codec: Option<PayloadCodec>,
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    #[serde(default)]
+    pub archive_dir: Option<String>,
+}
+
+// 继承顶层配置后, 单个交易所接收/发布管道的完整配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeConfig {
+    pub name: String,
+    pub stream_key: String,
+    pub topic: String,
+    pub zmq: ZmqProxyConfig,
+    pub max_stream_size: usize,
+    pub codec: PayloadCodec,
+    // None 表示不重新压缩, 见 `RedisPubberConfig::compression`
+    pub compression: Option<Compression>,
+    pub archive_dir: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RedisConfig {
+    #[serde(default = "default_is_primary")]
+    pub is_primary: bool,
+    // 单交易所的旧写法, 配置了 exchanges 时忽略
+    #[serde(default)]
     pub exchange: String,
+    #[serde(default)]
+    pub exchanges: Vec<ExchangeEntry>,
+    #[serde(default)]
+    pub ha: HaConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub sequencer: SequencerConfig,
+    #[serde(default)]
+    pub hold_back: HoldBackConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub decode: DecodeConfig,
+    #[serde(default)]
+    pub zmq_proxy: ZmqProxyConfig,
     pub redis_pubber: RedisPubberConfig,
+    // 日志级别, 支持 env_logger 过滤语法, 命令行 --log-level 优先
+    #[serde(default)]
+    pub log_level: Option<String>,
 }
 
 impl RedisConfig {
@@ -38,7 +552,254 @@ impl RedisConfig {
         if let Mode::Error = config.redis_pubber.mode {
             anyhow::bail!("Invalid mode in configuration");
         }
+
+        let exchanges = config.exchanges();
+        if exchanges.is_empty() {
+            anyhow::bail!("Either exchange or exchanges must be configured");
+        }
+        for (i, exchange) in exchanges.iter().enumerate() {
+            if exchange.zmq.hwm <= 0 {
+                anyhow::bail!("zmq hwm of exchange {} must be positive", exchange.name);
+            }
+            if exchange.zmq.dual_feed
+                && (exchange.zmq.primary_addr.is_none() || exchange.zmq.secondary_addr.is_none())
+            {
+                anyhow::bail!("zmq dual_feed of exchange {} requires primary_addr and secondary_addr", exchange.name);
+            }
+            if exchange.zmq.queue_size == 0 {
+                anyhow::bail!("zmq queue_size of exchange {} must be positive", exchange.name);
+            }
+            if exchanges[..i].iter().any(|e| e.name == exchange.name) {
+                anyhow::bail!("Duplicate exchange name: {}", exchange.name);
+            }
+            if exchanges[..i].iter().any(|e| e.stream_key == exchange.stream_key) {
+                anyhow::bail!("Duplicate stream key: {}", exchange.stream_key);
+            }
+            // 同一组接入地址共用一个接收器, 按 topic 分发; 接收器按第一个交易所的
+            // hwm 和 dual_feed 创建, 其他交易所必须一致
+            for other in exchanges[..i].iter().filter(|e| e.zmq.endpoints() == exchange.zmq.endpoints()) {
+                if other.topic == exchange.topic {
+                    anyhow::bail!("Duplicate topic {} on endpoints {:?}", exchange.topic, exchange.zmq.endpoints());
+                }
+                if other.zmq.hwm != exchange.zmq.hwm || other.zmq.dual_feed != exchange.zmq.dual_feed {
+                    anyhow::bail!(
+                        "Exchanges {} and {} share endpoints {:?} but differ in zmq hwm or dual_feed",
+                        other.name,
+                        exchange.name,
+                        exchange.zmq.endpoints()
+                    );
+                }
+            }
+        }
+
+        match config.redis_pubber.topology {
+            RedisTopology::Sentinel => {
+                if config.redis_pubber.sentinel_master.is_none() || config.redis_pubber.nodes.is_empty() {
+                    anyhow::bail!("sentinel topology requires sentinel_master and nodes");
+                }
+            }
+            RedisTopology::Cluster | RedisTopology::Standalone => {}
+        }
+
+        if config.watchdog.enabled
+            && (config.watchdog.period_interval_secs == 0 || config.watchdog.silence_intervals == 0)
+        {
+            anyhow::bail!("watchdog.period_interval_secs and watchdog.silence_intervals must be positive");
+        }
+
+        if let Some(sha) = &config.redis_pubber.script_sha {
+            if !sha.eq_ignore_ascii_case(crate::push_msg_sha()) {
+                anyhow::bail!(
+                    "redis_pubber.script_sha {} does not match the embedded push_msg.lua ({})",
+                    sha,
+                    crate::push_msg_sha()
+                );
+            }
+        }
+
+        if config.sequencer.enabled && config.sequencer.window == 0 {
+            anyhow::bail!("sequencer.window must be positive");
+        }
+
+        if config.decode.nesting_limit <= 0 || config.decode.traversal_limit_words == Some(0) {
+            anyhow::bail!("decode.nesting_limit and decode.traversal_limit_words must be positive");
+        }
+
+        if let Some(compression) = &config.redis_pubber.compression {
+            compression.validate()?;
+        }
+        for entry in &config.exchanges {
+            if let Some(compression) = &entry.compression {
+                compression.validate()?;
+            }
+        }
+
+        if config.validation.enabled && config.validation.period_ms <= 0 {
+            anyhow::bail!("validation.period_ms must be positive");
+        }
+
+        if config.ha.enabled && config.ha.renew_interval_ms >= config.ha.lease_ttl_ms {
+            anyhow::bail!("ha.renew_interval_ms must be smaller than ha.lease_ttl_ms");
+        }
         
         Ok(config)
     }
+
+    /// 展开后的交易所列表
+    ///
+    /// 没有配置 `exchanges` 时, 用顶层的 `exchange`, `zmq_proxy` 和
+    /// `redis_pubber` 组成单个交易所, 与旧配置保持兼容。
+    pub fn exchanges(&self) -> Vec<ExchangeConfig> {
+        let default_codec = if self.redis_pubber.publish_as_protobuf {
+            PayloadCodec::Protobuf
+        } else {
+            PayloadCodec::Capnp
+        };
+        if self.exchanges.is_empty() {
+            if self.exchange.is_empty() {
+                return Vec::new();
+            }
+            return vec![ExchangeConfig {
+                name: self.exchange.clone(),
+                stream_key: self.exchange.clone(),
+                topic: self.exchange.clone(),
+                zmq: self.zmq_proxy.clone(),
+                max_stream_size: self.redis_pubber.max_stream_size,
+                codec: default_codec,
+                compression: self.redis_pubber.compression.map(|c| c.compression()),
+                archive_dir: self.redis_pubber.archive_dir.clone(),
+            }];
+        }
+        self.exchanges
+            .iter()
+            .map(|entry| ExchangeConfig {
+                name: entry.name.clone(),
+                stream_key: entry.stream_key.clone().unwrap_or_else(|| entry.name.clone()),
+                topic: entry.topic.clone().unwrap_or_else(|| entry.name.clone()),
+                zmq: entry.zmq.clone().unwrap_or_else(|| self.zmq_proxy.clone()),
+                max_stream_size: entry.max_stream_size.unwrap_or(self.redis_pubber.max_stream_size),
+                codec: entry.codec.unwrap_or(default_codec),
+                compression: entry
+                    .compression
+                    .or(self.redis_pubber.compression)
+                    .map(|c| c.compression()),
+                archive_dir: entry
+                    .archive_dir
+                    .clone()
+                    .unwrap_or_else(|| self.redis_pubber.archive_dir.clone()),
+            })
+            .collect()
+    }
+
+    pub fn find_exchange(&self, name: &str) -> Option<ExchangeConfig> {
+        self.exchanges().into_iter().find(|e| e.name == name)
+    }
+
+    /// 逐项比较两份配置, 返回 "路径: 旧值 -> 新值" 形式的差异, 密码不输出明文
+    pub fn diff(&self, other: &RedisConfig) -> Vec<String> {
+        let old = serde_yaml::to_value(self).unwrap_or(Value::Null);
+        let new = serde_yaml::to_value(other).unwrap_or(Value::Null);
+        let mut changes = Vec::new();
+        diff_value("", &old, &new, &mut changes);
+        changes
+    }
+
+    /// 热加载时把需要重启才能生效的配置恢复为旧值, 返回被忽略的配置项
+    pub fn carry_over_restart_only(&mut self, old: &RedisConfig) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        if self.is_primary != old.is_primary {
+            ignored.push("is_primary");
+            self.is_primary = old.is_primary;
+        }
+        if self.redis_pubber.mode != old.redis_pubber.mode {
+            ignored.push("redis_pubber.mode");
+            self.redis_pubber.mode = old.redis_pubber.mode.clone();
+        }
+        if self.zmq_proxy != old.zmq_proxy {
+            ignored.push("zmq_proxy");
+            self.zmq_proxy = old.zmq_proxy.clone();
+        }
+        if self.ha != old.ha {
+            ignored.push("ha");
+            self.ha = old.ha.clone();
+        }
+        if self.watchdog != old.watchdog {
+            ignored.push("watchdog");
+            self.watchdog = old.watchdog.clone();
+        }
+        if self.sequencer != old.sequencer {
+            ignored.push("sequencer");
+            self.sequencer = old.sequencer.clone();
+        }
+        if self.hold_back != old.hold_back {
+            ignored.push("hold_back");
+            self.hold_back = old.hold_back.clone();
+        }
+        if self.stats != old.stats {
+            ignored.push("stats");
+            self.stats = old.stats.clone();
+        }
+        // 每个交易所的管道和接收器在启动时创建; 主备模式下租约和
+        // fencing key 还绑定在启动时的 stream 上
+        let (new_exchanges, old_exchanges) = (self.exchanges(), old.exchanges());
+        let pipelines_changed = new_exchanges.len() != old_exchanges.len()
+            || new_exchanges.iter().zip(&old_exchanges).any(|(n, o)| {
+                n.name != o.name
+                    || n.topic != o.topic
+                    || n.zmq != o.zmq
+                    || (old.ha.enabled && n.stream_key != o.stream_key)
+            });
+        if pipelines_changed {
+            ignored.push("exchange/exchanges");
+            self.exchange = old.exchange.clone();
+            self.exchanges = old.exchanges.clone();
+            self.zmq_proxy = old.zmq_proxy.clone();
+        }
+        ignored
+    }
+}
+
+impl RedisPubberConfig {
+    /// 地址, 账号密码, 拓扑或 TLS 配置变化时需要重建连接
+    pub fn connection_changed(&self, other: &RedisPubberConfig) -> bool {
+        self.host != other.host
+            || self.port != other.port
+            || self.username != other.username
+            || self.password != other.password
+            || self.topology != other.topology
+            || self.nodes != other.nodes
+            || self.sentinel_master != other.sentinel_master
+            || self.sentinel_password != other.sentinel_password
+            || self.tls != other.tls
+    }
+}
+
+fn diff_value(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Mapping(old_map), Value::Mapping(new_map)) => {
+            let mut keys: Vec<&Value> = old_map.keys().collect();
+            keys.extend(new_map.keys().filter(|k| !old_map.contains_key(*k)));
+            for key in keys {
+                let name = key.as_str().map(str::to_string).unwrap_or_else(|| format!("{:?}", key));
+                let child = if path.is_empty() { name } else { format!("{}.{}", path, name) };
+                let old_child = old_map.get(key).unwrap_or(&Value::Null);
+                let new_child = new_map.get(key).unwrap_or(&Value::Null);
+                diff_value(&child, old_child, new_child, changes);
+            }
+        }
+        _ if old != new => {
+            if path.ends_with("password") {
+                changes.push(format!("{}: *** -> ***", path));
+            } else {
+                changes.push(format!("{}: {} -> {}", path, format_value(old), format_value(new)));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    serde_yaml::to_string(value)
+        .map(|s| s.trim_end().to_string())
+        .unwrap_or_else(|_| format!("{:?}", value))
 } 
\ No newline at end of file
@@ -0,0 +1,171 @@
+// Redis 连接: 支持单机, Sentinel 主节点发现和 Cluster, 以及 rediss:// TLS
+use anyhow::Result;
+use log::info;
+use redis::aio::{ConnectionLike, ConnectionManager};
+use redis::cluster::ClusterClientBuilder;
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{SentinelClientBuilder, SentinelServerType};
+use redis::{Client, ClientTlsConfig, Cmd, ConnectionAddr, Pipeline, RedisFuture, TlsCertificates, TlsMode, Value};
+
+use crate::config::{RedisPubberConfig, RedisTopology, TlsConfig};
+
+/// 发布和选主共用的连接, 单机和 Sentinel 使用自动重连的 ConnectionManager
+#[derive(Clone)]
+pub enum RedisConn {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for RedisConn {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            RedisConn::Single(conn) => conn.req_packed_command(cmd),
+            RedisConn::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            RedisConn::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            RedisConn::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            RedisConn::Single(conn) => conn.get_db(),
+            RedisConn::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+fn load_certificates(tls: &TlsConfig) -> Result<TlsCertificates> {
+    let root_cert = match &tls.ca_cert {
+        Some(path) => Some(std::fs::read(path)?),
+        None => None,
+    };
+    let client_tls = match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => Some(ClientTlsConfig {
+            client_cert: std::fs::read(cert)?,
+            client_key: std::fs::read(key)?,
+        }),
+        (None, None) => None,
+        _ => anyhow::bail!("tls.client_cert and tls.client_key must be set together"),
+    };
+    Ok(TlsCertificates { client_tls, root_cert })
+}
+
+// "host:port" 形式的节点地址
+fn parse_node(node: &str) -> Result<(String, u16)> {
+    let (host, port) = node
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Invalid redis node address: {}", node))?;
+    Ok((host.to_string(), port.parse()?))
+}
+
+fn node_url(cfg: &RedisPubberConfig, host: &str, port: &str) -> String {
+    let tls = cfg.tls.as_ref().filter(|t| t.enabled);
+    let scheme = if tls.is_some() { "rediss" } else { "redis" };
+    let insecure = if tls.is_some_and(|t| t.insecure) { "#insecure" } else { "" };
+    format!(
+        "{}://{}:{}@{}:{}/{}",
+        scheme, cfg.username, cfg.password, host, port, insecure
+    )
+}
+
+async fn connect_standalone(cfg: &RedisPubberConfig) -> Result<RedisConn> {
+    let url = node_url(cfg, &cfg.host, &cfg.port);
+    let client = match cfg.tls.as_ref().filter(|t| t.enabled) {
+        Some(tls) => Client::build_with_tls(url, load_certificates(tls)?)?,
+        None => Client::open(url)?,
+    };
+    // 创建连接管理器
+    Ok(RedisConn::Single(ConnectionManager::new(client).await?))
+}
+
+async fn connect_sentinel(cfg: &RedisPubberConfig) -> Result<RedisConn> {
+    let master = cfg
+        .sentinel_master
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("sentinel_master is required for sentinel topology"))?;
+    let sentinels = cfg
+        .nodes
+        .iter()
+        .map(|node| parse_node(node).map(|(host, port)| ConnectionAddr::Tcp(host, port)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut builder = SentinelClientBuilder::new(sentinels, master.clone(), SentinelServerType::Master)?
+        .set_client_to_redis_username(cfg.username.clone())
+        .set_client_to_redis_password(cfg.password.clone());
+    if let Some(password) = &cfg.sentinel_password {
+        builder = builder.set_client_to_sentinel_password(password.clone());
+    }
+    if let Some(tls) = cfg.tls.as_ref().filter(|t| t.enabled) {
+        let mode = if tls.insecure { TlsMode::Insecure } else { TlsMode::Secure };
+        builder = builder
+            .set_client_to_redis_tls_mode(mode)
+            .set_client_to_redis_certificates(load_certificates(tls)?);
+    }
+
+    let client = builder.build()?.async_get_client().await?;
+    info!("Sentinel 发现主节点: {} -> {:?}", master, client.get_connection_info().addr);
+    Ok(RedisConn::Single(ConnectionManager::new(client).await?))
+}
+
+async fn connect_cluster(cfg: &RedisPubberConfig) -> Result<RedisConn> {
+    let nodes = if cfg.nodes.is_empty() {
+        vec![node_url(cfg, &cfg.host, &cfg.port)]
+    } else {
+        cfg.nodes
+            .iter()
+            .map(|node| parse_node(node).map(|(host, port)| node_url(cfg, &host, &port.to_string())))
+            .collect::<Result<Vec<_>>>()?
+    };
+    let mut builder = ClusterClientBuilder::new(nodes)
+        .username(cfg.username.clone())
+        .password(cfg.password.clone());
+    if let Some(tls) = cfg.tls.as_ref().filter(|t| t.enabled) {
+        let mode = if tls.insecure { TlsMode::Insecure } else { TlsMode::Secure };
+        builder = builder.tls(mode).certs(load_certificates(tls)?);
+    }
+    let conn = builder.build()?.get_async_connection().await?;
+    Ok(RedisConn::Cluster(conn))
+}
+
+/// 按 `redis_pubber.topology` 建立连接
+pub async fn connect(cfg: &RedisPubberConfig) -> Result<RedisConn> {
+    // 使用账号和密码连接
+    info!(
+        "Connecting to Redis with authentication, topology: {:?}, tls: {}",
+        cfg.topology,
+        cfg.tls.as_ref().is_some_and(|t| t.enabled)
+    );
+    match cfg.topology {
+        RedisTopology::Standalone => connect_standalone(cfg).await,
+        RedisTopology::Sentinel => connect_sentinel(cfg).await,
+        RedisTopology::Cluster => connect_cluster(cfg).await,
+    }
+}
+
+/// Lua 脚本访问的附属 key, 与 stream 使用同一个 hash tag, 集群模式下落在同一个 slot
+///
+/// stream key 自带 `{tag}` 时沿用其中的 tag, 否则以整个 stream key 作为 tag。
+pub fn aux_key(stream: &str, suffix: &str) -> String {
+    format!("{{{}}}:{}", hash_tag(stream), suffix)
+}
+
+fn hash_tag(key: &str) -> &str {
+    if let Some(start) = key.find('{') {
+        if let Some(len) = key[start + 1..].find('}') {
+            if len > 0 {
+                return &key[start + 1..start + 1 + len];
+            }
+        }
+    }
+    key
+}
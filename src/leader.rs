@@ -0,0 +1,173 @@
+// 主备高可用: 基于 Redis 租约的选主, 只有持有租约的实例才写 Stream
+use anyhow::Result;
+use log::{info, warn, error};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::HaConfig;
+use crate::connection::{aux_key, RedisConn};
+
+// 获取或续约租约, 返回当前持有的 fencing token, 0 表示租约被其他实例持有
+// KEYS[1]: 租约key, KEYS[2]: fencing token key
+// ARGV[1]: 实例id, ARGV[2]: 租约时长(毫秒)
+const ACQUIRE_LEASE_SCRIPT: &str = r#"
+local holder = redis.call("GET", KEYS[1])
+if holder == ARGV[1] then
+    redis.call("PEXPIRE", KEYS[1], ARGV[2])
+    return tonumber(redis.call("GET", KEYS[2]) or "0")
+end
+if redis.call("SET", KEYS[1], ARGV[1], "NX", "PX", ARGV[2]) then
+    return redis.call("INCR", KEYS[2])
+end
+return 0
+"#;
+
+// 仅在自己持有租约时释放
+const RELEASE_LEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+pub struct LeaderLease {
+    lease_key: String,
+    fence_key: String,
+    instance_id: String,
+    is_primary: bool,
+    ttl: Duration,
+    renew_interval: Duration,
+    // 0 表示当前不是主
+    token: AtomicU64,
+    last_renewed: Mutex<Option<Instant>>,
+}
+
+impl LeaderLease {
+    pub fn new(stream: &str, is_primary: bool, cfg: &HaConfig) -> Self {
+        // 与 stream 使用同一个 hash tag, 集群模式下落在同一个 slot
+        Self {
+            lease_key: aux_key(stream, "leader"),
+            fence_key: aux_key(stream, "fence"),
+            instance_id: instance_id(),
+            is_primary,
+            ttl: Duration::from_millis(cfg.lease_ttl_ms),
+            renew_interval: Duration::from_millis(cfg.renew_interval_ms),
+            token: AtomicU64::new(0),
+            last_renewed: Mutex::new(None),
+        }
+    }
+
+    pub fn fence_key(&self) -> &str {
+        &self.fence_key
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.token() != 0
+    }
+
+    /// 当前持有的 fencing token, 0 表示不是主
+    pub fn token(&self) -> u64 {
+        self.token.load(Ordering::Acquire)
+    }
+
+    /// 发布脚本返回 FENCED 时调用, 说明已有更新的主接管
+    pub fn mark_fenced(&self) {
+        if self.token.swap(0, Ordering::AcqRel) != 0 {
+            warn!("实例 {} 的 fencing token 已过期, 降级为备", self.instance_id);
+        }
+    }
+
+    /// 尝试获取或续约一次租约
+    pub async fn try_acquire(&self, conn: &mut RedisConn) -> Result<bool> {
+        let token: u64 = redis::cmd("EVAL")
+            .arg(ACQUIRE_LEASE_SCRIPT)
+            .arg(2)
+            .arg(&self.lease_key)
+            .arg(&self.fence_key)
+            .arg(&self.instance_id)
+            .arg(self.ttl.as_millis() as u64)
+            .query_async(conn)
+            .await?;
+
+        let previous = self.token.swap(token, Ordering::AcqRel);
+        if token != 0 {
+            *self.last_renewed.lock().unwrap() = Some(Instant::now());
+            if previous == 0 {
+                info!("实例 {} 成为主, fencing token: {}", self.instance_id, token);
+            }
+        } else if previous != 0 {
+            warn!("实例 {} 失去租约, 降级为备", self.instance_id);
+        }
+        Ok(token != 0)
+    }
+
+    async fn release(&self, conn: &mut RedisConn) -> Result<()> {
+        let _: i64 = redis::cmd("EVAL")
+            .arg(RELEASE_LEASE_SCRIPT)
+            .arg(1)
+            .arg(&self.lease_key)
+            .arg(&self.instance_id)
+            .query_async(conn)
+            .await?;
+        self.token.store(0, Ordering::Release);
+        Ok(())
+    }
+
+    /// 租约续约循环, 直到收到关闭信号
+    ///
+    /// 备实例启动时先等待一个租约周期, 保证冷启动时由主实例优先拿到租约;
+    /// 之后主备都按 `renew_interval_ms` 尝试获取, 主的租约过期后备最多在
+    /// `lease_ttl_ms + renew_interval_ms` 内接管。
+    pub async fn run<F>(&self, current_conn: F, shutdown: CancellationToken)
+    where
+        F: Fn() -> RedisConn,
+    {
+        if !self.is_primary {
+            info!("备实例 {} 等待 {:?} 后开始竞选", self.instance_id, self.ttl);
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = tokio::time::sleep(self.ttl) => {}
+            }
+        }
+
+        let mut ticker = tokio::time::interval(self.renew_interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            if let Err(e) = self.try_acquire(&mut current_conn()).await {
+                error!("续约租约失败: {}", e);
+                // 无法确认租约时, 超过租约时长就主动降级, 避免双主
+                let expired = self
+                    .last_renewed
+                    .lock()
+                    .unwrap()
+                    .is_none_or(|t| t.elapsed() >= self.ttl);
+                if expired && self.token.swap(0, Ordering::AcqRel) != 0 {
+                    warn!("实例 {} 超过 {:?} 未能续约, 降级为备", self.instance_id, self.ttl);
+                }
+            }
+        }
+
+        if self.is_leader() {
+            match self.release(&mut current_conn()).await {
+                Ok(_) => info!("实例 {} 已释放租约", self.instance_id),
+                Err(e) => warn!("释放租约失败: {}", e),
+            }
+        }
+    }
+}
+
+fn instance_id() -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("{}-{}", host, std::process::id())
+}
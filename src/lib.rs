@@ -1,170 +1,101 @@
 use anyhow::Result;
-use redis::{Client, aio::ConnectionManager};
-use log::{info};
+use log::{info, debug, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+use tokio_util::sync::CancellationToken;
 
+pub mod arbiter;
+pub mod backfill;
+pub mod book;
+pub mod codec;
+pub mod compression;
 mod config;
+pub mod connection;
+pub mod leader;
+pub mod logging;
 mod message;
+pub mod pipeline;
 mod proto;
+pub mod queue;
 pub mod receiver;
+pub mod sequencer;
+pub mod stats;
+pub mod validate;
+pub mod view;
+pub mod watchdog;
 
 // Cap'n Proto generated code
 pub mod period_capnp {
     include!(concat!(env!("OUT_DIR"), "/period_capnp.rs"));
 }
 
-pub use config::{RedisConfig, Mode};
-pub use message::PeriodMessage;
+pub use backfill::{ArchivedPeriod, backfill_from_archive, list_archive};
+pub use arbiter::{ArbitratedPeriod, PeriodArbiter};
+pub use book::{OrderBook, OrderBooks, Price};
+pub use codec::{Codec, CodecKind};
+pub use compression::{decompress_auto, Compression, CompressionConfig, CompressionKind};
+pub use config::{ArbiterConfig, DecodeConfig, ExchangeConfig, ExchangeEntry, HaConfig, HoldBackConfig, OverflowPolicy, PayloadCodec, RedisConfig, RedisTopology, Mode, SequencerConfig, StatsConfig, TlsConfig, ValidationConfig, WatchdogConfig, ZmqProxyConfig};
+pub use connection::{aux_key, RedisConn};
+pub use leader::LeaderLease;
+pub use message::{BookUpdate, IncrementOrderBookInfo, LevelAction, PeriodMessage, PriceLevel, Side, SymbolInfo, TradeInfo};
 pub use proto::message_old;
-pub use receiver::ZmqReceiver;
-
-// 嵌入Lua脚本
-const PUSH_MSG_SCRIPT: &str = r#"
-local stream = KEYS[1]
-local new_id = ARGV[1]
-local new_info_count = tonumber(ARGV[2])
-local new_msg_content = ARGV[3]
-local max_stream_size = tonumber(ARGV[4]) or 1000 
-
-local logs = {}
-local function log(msg)
-    table.insert(logs, msg)
-end
-
-log("stream: " .. stream)
-log("new_id: " .. new_id)
-log("new_info_count: " .. new_info_count)
-log("msg_content_length: " .. string.len(new_msg_content))
-log("max_stream_size: " .. max_stream_size)
-
-local current_size = redis.call("XLEN", stream)
-log("当前Stream大小: " .. current_size)
-if current_size >= max_stream_size then
-    -- 删除最旧的一条
-    local oldest_msg = redis.call("XRANGE", stream, "-", "+", "COUNT", 1)
-    if #oldest_msg > 0 then
-        log("触发FIFO清理, 当前大小=" .. current_size .. "，限制=" .. max_stream_size)
-        redis.call("XDEL", stream, oldest_msg[1][1])
-    end
-end
-log("Stream 数量小于限制，不进行清理")
-
--- 1. 提取 period (如 "100")
-local dash_pos = string.find(new_id, "-")
-if not dash_pos then
-    log("INVALID_KEY_FORMAT")
-    return {err = "INVALID_KEY_FORMAT"}
-end
-local new_period = string.sub(new_id, dash_pos + 1)
-
--- 2. 查找相同 period 的消息
-local msgs = redis.call("XREVRANGE", stream, "+", "-", "COUNT", 100)  -- 查最新 100 条，确保不遗漏
-log("查询到的消息数量: " .. #msgs)
-local max_info_count = 0
-local same_period_msgs = {}  -- 存储相同period的所有消息
-
-for i, msg in ipairs(msgs) do
-    local fields = msg[2]
-    -- Redis Stream字段是以数组形式存储的: [field1, value1, field2, value2, ...]
-    local msg_key = nil
-    for j = 1, #fields, 2 do
-        local field_name = fields[j]
-        local field_value = fields[j + 1]
-        if field_name == "key" then
-            msg_key = field_value
-        end
-    end
-    if msg_key then
-        local msg_dash_pos = string.find(msg_key, "-")
-        if msg_dash_pos then
-            local msg_period = string.sub(msg_key, msg_dash_pos + 1)
-            if msg_period == new_period then
-                log("找到相同 period 的消息: " .. msg_key)
-                -- 从字段数组中查找info_count
-                local current_count = 0
-                for j = 1, #fields, 2 do
-                    if fields[j] == "info_count" then
-                        current_count = tonumber(fields[j + 1]) or 0
-                        break
-                    end
-                end
-                log("旧消息的 info_count: " .. current_count)
-                
-                -- 记录这个相同period的消息
-                table.insert(same_period_msgs, {
-                    id = msg[1],
-                    info_count = current_count,
-                    key = msg_key
-                })
-                
-                if current_count > max_info_count then
-                    max_info_count = current_count
-                end
-            end
-        end
-    end
-end
-
--- 清理相同period的重复消息 只保留info_count最大的那一条
-if #same_period_msgs > 1 then
-    log("发现" .. #same_period_msgs .. "条相同period的消息 需要清理")
-    local keep_msg = nil
-    for _, msg_info in ipairs(same_period_msgs) do
-        if msg_info.info_count == max_info_count then
-            if keep_msg == nil then
-                keep_msg = msg_info  -- 保留第一个达到最大info_count的消息
-                log("保留消息: " .. msg_info.id .. " (info_count=" .. msg_info.info_count .. ")")
-            else
-                -- 删除重复的消息
-                log("删除重复消息: " .. msg_info.id .. " (info_count=" .. msg_info.info_count .. ")")
-                redis.call("XDEL", stream, msg_info.id)
-            end
-        else
-            -- 删除info_count较小的消息
-            log("删除旧消息: " .. msg_info.id .. " (info_count=" .. msg_info.info_count .. ")")
-            redis.call("XDEL", stream, msg_info.id)
-        end
-    end
-elseif #same_period_msgs == 1 then
-    log("找到1条相同period的消息 无需清理")
-else
-    log("没有找到相同period的消息")
-end
-
--- 3. 判断是否要更新
-if new_info_count > max_info_count then
-    -- 删除所有相同period的旧消息
-    for _, msg_info in ipairs(same_period_msgs) do
-        log("删除旧消息以便更新: " .. msg_info.id)
-        redis.call("XDEL", stream, msg_info.id)
-    end
-    -- 写入新消息
-    log("写入新消息: " .. new_id)
-    local operation_type = (#same_period_msgs > 0) and "UPDATE" or "INSERT"
-    redis.call("XADD", stream, "*",
-        "operation", operation_type,
-        "key", new_id,
-        "info_count", new_info_count,
-        "msg_content", new_msg_content,
-        "replaced_count", #same_period_msgs
-    )
-    local log_string = table.concat(logs, "\n")
-    return "UPDATED\n" .. log_string
-elseif new_info_count == max_info_count then
-    log("新消息与最大信息数相同，跳过")
-    local log_string = table.concat(logs, "\n")
-    return "EQUAL\n" .. log_string
-else
-    log("新消息小于最大信息数，跳过")
-    local log_string = table.concat(logs, "\n")
-    return "SKIPPED\n" .. log_string
-end
-"#;
+pub use queue::{period_queue, QueueReceiver, QueueSender};
+pub use receiver::{ZmqMessage, ZmqReceiver};
+pub use sequencer::{PeriodSequencer, Sequenced};
+pub use stats::{Bar, BarAggregator, BarInterval, PeriodStats, SpreadStats, StatsEngine, SymbolStats};
+pub use validate::{Severity, ValidationReport, Violation, ViolationCounter, ViolationKind};
+pub use view::{summarize, DecodeError, PeriodSummary, PeriodView};
+pub use watchdog::{FeedAlarm, FeedStatus, FeedWatchdog};
+
+// 发布脚本, push_msg.lua 为唯一来源; 启动和重连时 SCRIPT LOAD, 发布时按 sha 调用 EVALSHA
+const PUSH_MSG_SCRIPT: &str = include_str!("../push_msg.lua");
+
+// 脚本内容的 sha1, 与 SCRIPT LOAD 的返回值一致
+static PUSH_MSG_SHA: LazyLock<String> = LazyLock::new(|| redis::Script::new(PUSH_MSG_SCRIPT).get_hash().to_string());
+
+/// 发布脚本的 sha1
+pub fn push_msg_sha() -> &'static str {
+    &PUSH_MSG_SHA
+}
+
+// 把发布脚本载入 Redis 的脚本缓存; 与期望版本的比对见配置项 `redis_pubber.script_sha`
+async fn load_push_script(conn: &mut RedisConn) -> Result<()> {
+    let sha: String = redis::cmd("SCRIPT")
+        .arg("LOAD")
+        .arg(PUSH_MSG_SCRIPT)
+        .query_async(conn)
+        .await?;
+    debug!("发布脚本已载入, sha: {}", sha);
+    Ok(())
+}
+
+// Stream 条目类型, 对应发布脚本的 ARGV[7]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    Data,
+    Gap,
+    GapRange,
+    Heartbeat,
+}
+
+impl EntryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Data => "data",
+            EntryKind::Gap => "gap",
+            EntryKind::GapRange => "gap_range",
+            EntryKind::Heartbeat => "heartbeat",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct MktArchiveMsg {
     pub key: String,
     pub info_count: u64,
     pub msg_content: Vec<u8>,
+    pub codec: PayloadCodec,
+    pub kind: EntryKind,
 }
 
 impl MktArchiveMsg {
@@ -173,56 +104,362 @@ impl MktArchiveMsg {
             key: format!("{}-{}", post_ts, period),
             info_count,
             msg_content,
+            codec: PayloadCodec::Capnp,
+            kind: EntryKind::Data,
+        }
+    }
+
+    /// 确认缺失的 period 的缺口标记, 之后到达的行情数据会替换它
+    pub fn gap(period: i64, codec: PayloadCodec) -> Self {
+        Self {
+            key: format!("{}-{}", chrono::Utc::now().timestamp_millis(), period),
+            info_count: 0,
+            msg_content: Vec::new(),
+            codec,
+            kind: EntryKind::Gap,
+        }
+    }
+
+    /// 一次缺失过多 period 时的范围缺口标记, `first` 和 `last` 都包含在内
+    pub fn gap_range(first: i64, last: i64, codec: PayloadCodec) -> Self {
+        Self {
+            key: first.to_string(),
+            info_count: 0,
+            msg_content: last.to_string().into_bytes(),
+            codec,
+            kind: EntryKind::GapRange,
+        }
+    }
+
+    /// 上游断流时写入的心跳标记, 记录最后收到的 period 和告警原因
+    ///
+    /// 心跳写入 `aux_key(stream, "heartbeat")` 心跳 Stream, 不进入行情 Stream。
+    pub fn heartbeat(last_period: Option<i64>, reason: &str) -> Self {
+        Self {
+            key: last_period.map(|p| p.to_string()).unwrap_or_default(),
+            info_count: 0,
+            msg_content: reason.as_bytes().to_vec(),
+            codec: PayloadCodec::Capnp,
+            kind: EntryKind::Heartbeat,
         }
     }
+
+    pub fn with_codec(mut self, codec: PayloadCodec) -> Self {
+        self.codec = codec;
+        self
+    }
 }
 
+// 热加载时需要一起替换的配置和连接
+struct PubberState {
+    config: Arc<RedisConfig>,
+    conn: RedisConn,
+}
+
+/// 所有交易所共享一个 Redis 连接, 每次发布按交易所名查找当前配置
 pub struct RedisStreamMktPubber {
-    conn_manager: ConnectionManager,
-    config: RedisConfig,
+    cfg_path: String,
+    state: Arc<RwLock<PubberState>>,
+    // 交易所名 -> 租约, 未开启主备时为空, 总是写入
+    leases: HashMap<String, Arc<LeaderLease>>,
 }
 
 impl RedisStreamMktPubber {
     pub async fn new(cfg_path: &str) -> Result<Self> {
         let config = RedisConfig::from_file(cfg_path)?;
-        
-        // 使用账号和密码连接
-        info!("Connecting to Redis with authentication");
-        let client = Client::open(format!(
-            "redis://{}:{}@{}:{}/",
-            config.redis_pubber.username,
-            config.redis_pubber.password,
-            config.redis_pubber.host,
-            config.redis_pubber.port
-        ))?;
-
-        // 创建连接管理器
-        let conn_manager = ConnectionManager::new(client.clone()).await?;
+        let mut conn = connection::connect(&config.redis_pubber).await?;
+        info!("发布脚本 sha: {}", push_msg_sha());
+        load_push_script(&mut conn).await?;
+
+        let mut leases = HashMap::new();
+        if config.ha.enabled {
+            for exchange in config.exchanges() {
+                let lease = LeaderLease::new(&exchange.stream_key, config.is_primary, &config.ha);
+                leases.insert(exchange.name, Arc::new(lease));
+            }
+        }
 
         Ok(Self {
-            conn_manager,
-            config,
+            cfg_path: cfg_path.to_string(),
+            state: Arc::new(RwLock::new(PubberState {
+                config: Arc::new(config),
+                conn,
+            })),
+            leases,
         })
     }
 
-    pub async fn publish(&self, msg: MktArchiveMsg) -> Result<()> {
-        let keys = vec![self.config.exchange.clone()];
+    // 同一时刻的配置和连接
+    fn snapshot(&self) -> (Arc<RedisConfig>, RedisConn) {
+        let state = self.state.read().unwrap();
+        (state.config.clone(), state.conn.clone())
+    }
+
+    fn exchange_config(&self, exchange: &str) -> Result<(ExchangeConfig, RedisConn)> {
+        let (config, conn) = self.snapshot();
+        let exchange = config
+            .find_exchange(exchange)
+            .ok_or_else(|| anyhow::anyhow!("Unknown exchange: {}", exchange))?;
+        Ok((exchange, conn))
+    }
+
+    /// 重新读取配置文件并替换可以在线生效的配置, 返回变更列表
+    ///
+    /// 新配置校验失败或重连失败时保持旧配置不变。只有地址或账号密码变化时
+    /// 才会重建连接, 需要重启才能生效的配置项会被忽略并打印警告。
+    pub async fn reload(&self) -> Result<Vec<String>> {
+        let mut new_config = RedisConfig::from_file(&self.cfg_path)?;
+        let (old_config, old_conn) = self.snapshot();
+
+        for field in new_config.carry_over_restart_only(&old_config) {
+            warn!("配置项 {} 需要重启才能生效, 本次热加载忽略", field);
+        }
+        let changes = old_config.diff(&new_config);
+        if changes.is_empty() {
+            info!("配置文件无变化");
+            return Ok(changes);
+        }
+
+        let conn = if new_config.redis_pubber.connection_changed(&old_config.redis_pubber) {
+            info!("Redis 连接配置变化, 重建连接");
+            let mut conn = connection::connect(&new_config.redis_pubber).await?;
+            load_push_script(&mut conn).await?;
+            conn
+        } else {
+            old_conn
+        };
+
+        {
+            let mut state = self.state.write().unwrap();
+            state.config = Arc::new(new_config);
+            state.conn = conn;
+        }
+        for change in &changes {
+            info!("配置变更: {}", change);
+        }
+        Ok(changes)
+    }
+
+    /// Sentinel 模式下主从切换后旧主变为只读, 重新发现主节点并替换连接
+    async fn handle_failover(&self, err: &redis::RedisError) {
+        let (config, _) = self.snapshot();
+        if config.redis_pubber.topology != RedisTopology::Sentinel
+            || err.kind() != redis::ErrorKind::ReadOnly
+        {
+            return;
+        }
+        warn!("Redis 主节点已变为只读, 通过 Sentinel 重新发现主节点");
+        let mut conn = match connection::connect(&config.redis_pubber).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("重新发现主节点失败: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = load_push_script(&mut conn).await {
+            warn!("新主节点载入发布脚本失败, 发布时会重试: {}", e);
+        }
+        self.state.write().unwrap().conn = conn;
+    }
+
+    /// 开启主备时为每个交易所启动租约竞选
+    ///
+    /// 主实例会先同步尝试一次获取租约, 这样启动补数据时就已经是主。
+    /// `shutdown` 取消后释放租约, 返回的任务在释放完成后结束; 应在发布管道
+    /// 全部退出后再取消, 否则退出前发布的缓存 period 会被 fence 掉。
+    pub async fn start_leader_election(&self, shutdown: CancellationToken) -> Result<Vec<tokio::task::JoinHandle<()>>> {
+        let (config, _) = self.snapshot();
+        let mut handles = Vec::new();
+        for (exchange, lease) in &self.leases {
+            info!(
+                "主备模式开启, 交易所: {}, 实例: {}, is_primary: {}",
+                exchange,
+                lease.instance_id(),
+                config.is_primary
+            );
+            if config.is_primary {
+                if let Err(e) = lease.try_acquire(&mut self.snapshot().1).await {
+                    warn!("{} 首次获取租约失败, 将在续约循环中重试: {}", exchange, e);
+                }
+            }
+            let lease = lease.clone();
+            let state = self.state.clone();
+            let shutdown = shutdown.clone();
+            // 每次续约都取当前连接, 热加载或主从切换后自动使用新连接
+            handles.push(tokio::spawn(async move {
+                lease.run(move || state.read().unwrap().conn.clone(), shutdown).await;
+            }));
+        }
+        Ok(handles)
+    }
+
+    /// 未开启主备或当前持有该交易所的租约时返回 true
+    pub fn is_leader(&self, exchange: &str) -> bool {
+        self.leases.get(exchange).is_none_or(|l| l.is_leader())
+    }
+
+    /// 当前生效的配置, 热加载后返回新配置
+    pub fn config(&self) -> Arc<RedisConfig> {
+        self.state.read().unwrap().config.clone()
+    }
+
+    /// 按交易所配置把一个 period 组装成待发布的消息
+    ///
+    /// `raw` 是收到的压缩 capnp 数据; 交易所 codec 为 protobuf 时解码后转码为
+    /// protobuf, 按交易所的 compression 压缩, 默认为 zlib。capnp 不做完整解码:
+    /// 配置了 compression 时只解压后重新压缩, 否则原样发布。
+    ///
+    /// 转码时超过 `decode` 的限制且开启 `oversize_fallback` 时, 原样发布 capnp 数据。
+    pub fn build_archive_msg(&self, exchange: &str, summary: &PeriodSummary, raw: Vec<u8>) -> Result<MktArchiveMsg> {
+        let (config, _) = self.snapshot();
+        let (exchange, _) = self.exchange_config(exchange)?;
+        let mut codec = exchange.codec;
+        let content = match (exchange.codec, exchange.compression) {
+            (PayloadCodec::Protobuf, compression) => {
+                match PeriodMessage::from_capnp_with_options(&raw, true, config.decode.reader_options()) {
+                    Ok(msg) => msg.to_protobuf_with(compression.unwrap_or_default())?,
+                    Err(e) if config.decode.oversize_fallback && DecodeError::is_too_large(&e) => {
+                        warn!("[{}] period {} {}, 不转码, 原样发布 capnp", exchange.name, summary.period, e);
+                        codec = PayloadCodec::Capnp;
+                        raw
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            (PayloadCodec::Capnp, Some(compression)) => compression.compress(&decompress_auto(&raw)?)?,
+            (PayloadCodec::Capnp, None) => raw,
+        };
+        Ok(MktArchiveMsg::new(
+            summary.period,
+            summary.post_ts,
+            summary.info_count(),
+            content,
+        ).with_codec(codec))
+    }
+
+    /// 查询交易所 Stream 最近 100 条中行情消息的最大 period, 没有行情消息时返回 None
+    ///
+    /// 缺口替换和 UPDATE 会把较早的 period 追加到末尾, 所以取最大值而不是最后一条;
+    /// GAP 标记不算行情消息, 范围缺口标记和旧版本写入的心跳标记没有 key 字段, 都跳过。
+    pub async fn last_published_period(&self, exchange: &str) -> Result<Option<i64>> {
+        let (exchange, mut conn) = self.exchange_config(exchange)?;
+        let entries: redis::streams::StreamRangeReply = redis::cmd("XREVRANGE")
+            .arg(&exchange.stream_key)
+            .arg("+")
+            .arg("-")
+            .arg("COUNT")
+            .arg(100)
+            .query_async(&mut conn)
+            .await?;
+
+        let mut last = None;
+        for entry in &entries.ids {
+            if entry.get::<String>("operation").as_deref() == Some("GAP") {
+                continue;
+            }
+            let Some(key) = entry.get::<String>("key") else {
+                continue;
+            };
+            // key 格式为 "post_ts-period"
+            let period = key
+                .rsplit_once('-')
+                .and_then(|(_, p)| p.parse::<i64>().ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid key format in stream: {}", key))?;
+            last = last.max(Some(period));
+        }
+        Ok(last)
+    }
+
+    /// 把一条统计写入交易所的统计 Stream, 备实例不写
+    ///
+    /// key 与行情 Stream 使用同一个 hash tag, 见 [`aux_key`]。`kind` 为 "period"
+    /// 或 K 线周期如 "1m", `ts` 为 period 的 ts 或 K 线开始时间, `data` 为 JSON。
+    pub async fn publish_stats(&self, exchange: &str, kind: &str, ts: i64, data: &str) -> Result<()> {
+        if !self.is_leader(exchange) {
+            return Ok(());
+        }
+        let (config, mut conn) = self.snapshot();
+        let exchange = config
+            .find_exchange(exchange)
+            .ok_or_else(|| anyhow::anyhow!("Unknown exchange: {}", exchange))?;
+        let result: redis::RedisResult<String> = redis::cmd("XADD")
+            .arg(aux_key(&exchange.stream_key, "stats"))
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(config.stats.max_stream_size)
+            .arg("*")
+            .arg("kind")
+            .arg(kind)
+            .arg("ts")
+            .arg(ts)
+            .arg("data")
+            .arg(data)
+            .query_async(&mut conn)
+            .await;
+        if let Err(e) = result {
+            self.handle_failover(&e).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    pub async fn publish(&self, exchange: &str, msg: MktArchiveMsg) -> Result<()> {
+        // 备实例照常接收解码, 但不写 Stream
+        if !self.is_leader(exchange) {
+            debug!("当前为备实例, 跳过发布: {} {}", exchange, msg.key);
+            return Ok(());
+        }
+
+        let lease = self.leases.get(exchange);
+        let (exchange, mut conn) = self.exchange_config(exchange)?;
+        // 心跳写入单独的 Stream, 避免长时间断流时挤掉行情 Stream 中的数据
+        let stream = match msg.kind {
+            EntryKind::Heartbeat => aux_key(&exchange.stream_key, "heartbeat"),
+            EntryKind::Data | EntryKind::Gap | EntryKind::GapRange => exchange.stream_key.clone(),
+        };
+        let mut keys = vec![stream];
+        let mut fence_token = None;
+        if let Some(lease) = lease {
+            keys.push(lease.fence_key().to_string());
+            fence_token = Some(lease.token());
+        }
         
-        let result: String = redis::cmd("EVAL")
-            .arg(PUSH_MSG_SCRIPT)
+        let mut cmd = redis::cmd("EVALSHA");
+        cmd.arg(push_msg_sha())
             .arg(keys.len())
             .arg(keys)
             .arg(&msg.key)
             .arg(msg.info_count.to_string())
             .arg(&msg.msg_content)
-            .arg(self.config.redis_pubber.max_stream_size.to_string())
-            .query_async(&mut self.conn_manager.clone())
-            .await?;
+            .arg(exchange.max_stream_size.to_string())
+            .arg(msg.codec.as_str());
+        // 未开启主备时脚本不读取 token, 用空串占位
+        cmd.arg(fence_token.map(|t| t.to_string()).unwrap_or_default())
+            .arg(msg.kind.as_str());
+        let mut result = cmd.query_async::<String>(&mut conn).await;
+        // 脚本缓存被清空或主从切换到没有载入过脚本的节点时, 重新载入后重试一次
+        if matches!(&result, Err(e) if e.kind() == redis::ErrorKind::NoScriptError) {
+            warn!("Redis 中没有发布脚本, 重新载入");
+            load_push_script(&mut conn).await?;
+            result = cmd.query_async(&mut conn).await;
+        }
+        let result = match result {
+            Ok(r) => r,
+            Err(e) => {
+                self.handle_failover(&e).await;
+                return Err(e.into());
+            }
+        };
 
         // 解析返回的字符串: 第一行是结果，后面的行是日志
         let lines: Vec<&str> = result.lines().collect();
-        if let Some(_) = lines.first() {
-            info!("=== Period行情发布日志 ===");
+        if lines.first() == Some(&"FENCED") {
+            if let Some(lease) = lease {
+                lease.mark_fenced();
+            }
+        }
+        if !lines.is_empty() {
+            info!("=== Period行情发布日志 [{}] ===", exchange.name);
             if lines.len() > 1 {
                 for (i, log_line) in lines[1..].iter().enumerate() {
                     info!("Log[{}]: {}", i, log_line);
@@ -230,9 +467,9 @@ impl RedisStreamMktPubber {
             }
             info!("=== 日志结束 ===");
         } else {
-            info!("Message published to stream {} with empty result", self.config.exchange);
+            info!("Message published to stream {} with empty result", exchange.stream_key);
         }
 
         Ok(())
     }
-} 
\ No newline at end of file
+}
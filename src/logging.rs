@@ -0,0 +1,57 @@
+// 可在运行时替换过滤规则的日志器, 供 SIGHUP 热加载调整日志级别
+use log::{Log, Metadata, Record};
+use std::sync::{OnceLock, RwLock};
+
+struct ReloadableLogger {
+    inner: RwLock<env_logger::Logger>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush()
+    }
+}
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+fn build(filter: &str) -> env_logger::Logger {
+    env_logger::Builder::new()
+        .parse_filters(filter)
+        .format(|buf, record| {
+            use std::io::Write;
+            writeln!(buf, "{}", record.args())
+        })
+        .build()
+}
+
+/// 初始化全局日志, `filter` 使用 env_logger 过滤语法
+pub fn init(filter: &str) {
+    let logger = build(filter);
+    let max_level = logger.filter();
+    let logger = LOGGER.get_or_init(|| ReloadableLogger {
+        inner: RwLock::new(logger),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// 替换日志过滤规则, 未初始化时等同于 init
+pub fn set_filter(filter: &str) {
+    match LOGGER.get() {
+        Some(logger) => {
+            let new_logger = build(filter);
+            log::set_max_level(new_logger.filter());
+            *logger.inner.write().unwrap() = new_logger;
+        }
+        None => init(filter),
+    }
+}
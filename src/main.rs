@@ -1,56 +1,271 @@
 use anyhow::Result;
-use log::{info, error};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::{info, error, warn};
 use mkt_pubber::receiver::ZmqReceiver;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::{select, sync::watch};
 use tokio::signal;
 use tokio_util::sync::CancellationToken;
 // 从 lib crate 导入
-use mkt_pubber::{PeriodMessage, MktArchiveMsg, RedisStreamMktPubber};
+use mkt_pubber::{backfill_from_archive, logging, period_queue, DecodeConfig, FeedWatchdog, Mode, OverflowPolicy, PeriodMessage, PeriodView, RedisConfig, RedisStreamMktPubber};
+use mkt_pubber::codec::normalize_to_capnp;
+use mkt_pubber::pipeline::run_pipeline;
+use mkt_pubber::watchdog::{run_watchdog, serve_health};
+
+#[derive(Parser)]
+#[command(name = "mkt_pubber", about = "把 period 行情发布到 Redis Stream")]
+struct Cli {
+    /// 配置文件路径
+    #[arg(long, global = true, default_value = "./mkt_cfg.yaml")]
+    config: String,
+
+    /// 日志级别, 支持 env_logger 的过滤语法, 如 info 或 mkt_pubber=debug;
+    /// 不指定时使用配置文件中的 log_level, 默认 info
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// 不指定子命令时等同于 run
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 接收 ZMQ 实时行情并发布到 Redis
+    Run,
+    /// 解码一个归档文件并打印内容
+    Inspect {
+        file: PathBuf,
+        /// 输出格式: table 为各 symbol 的条数, json 为完整内容, ndjson 为每行一条 trade 或 inc
+        #[arg(long, value_enum, default_value = "table")]
+        format: InspectFormat,
+    },
+    /// 把一个归档 period 发布到 Redis Stream
+    PublishFile {
+        file: PathBuf,
+        /// 目标交易所, 默认为配置中的第一个
+        #[arg(long)]
+        exchange: Option<String>,
+    },
+    /// 校验配置文件
+    ValidateConfig,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InspectFormat {
+    Table,
+    Json,
+    Ndjson,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // 先设置日志级别，再初始化日志
-    std::env::set_var("RUST_LOG", "info");
-    env_logger::Builder::from_default_env()
-        .format(|buf, record| {
-            use std::io::Write;
-            writeln!(buf, "{}", record.args())
-        })
-        .init();
+    let cli = Cli::parse();
+    let log_filter = cli
+        .log_level
+        .clone()
+        .or_else(|| RedisConfig::from_file(&cli.config).ok().and_then(|c| c.log_level))
+        .unwrap_or_else(|| "info".to_string());
+    logging::init(&log_filter);
+
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(&cli.config, cli.log_level.is_some()).await,
+        Command::Inspect { file, format } => inspect(&file, format),
+        Command::PublishFile { file, exchange } => publish_file(&cli.config, &file, exchange).await,
+        Command::ValidateConfig => validate_config(&cli.config),
+    }
+}
+
+fn inspect(file: &Path, format: InspectFormat) -> Result<()> {
+    // 支持 capnp, capnp packed 和 protobuf 以及各种压缩格式的文件
+    let data = normalize_to_capnp(std::fs::read(file)?.into(), &DecodeConfig::default())?.plain;
+    match format {
+        InspectFormat::Table => {
+            let view = PeriodView::new(&data, false)?;
+            view.print_info()?;
+            println!("total_info_count: {}", view.total_info_count()?);
+        }
+        InspectFormat::Json => {
+            println!("{}", PeriodMessage::from_capnp(&data, false)?.to_json_pretty()?);
+        }
+        InspectFormat::Ndjson => {
+            PeriodMessage::from_capnp(&data, false)?.write_ndjson(std::io::stdout().lock())?;
+        }
+    }
+    Ok(())
+}
+
+async fn publish_file(cfg_path: &str, file: &Path, exchange: Option<String>) -> Result<()> {
+    let publisher = RedisStreamMktPubber::new(cfg_path).await?;
+    let config = publisher.config();
+    if config.ha.enabled {
+        anyhow::bail!("publish-file 不参与主备竞选, 请使用 ha.enabled 为 false 的配置");
+    }
+    let exchange = match exchange {
+        Some(name) => config
+            .find_exchange(&name)
+            .ok_or_else(|| anyhow::anyhow!("配置中没有交易所 {}", name))?,
+        None => config.exchanges().remove(0),
+    };
+    let data = normalize_to_capnp(std::fs::read(file)?.into(), &config.decode)?;
+    let view = PeriodView::new(&data.plain, false)?;
+    view.print_info()?;
+    let summary = view.summary()?;
+    drop(view);
+    let archive_msg = publisher.build_archive_msg(&exchange.name, &summary, data.raw.to_vec())?;
+    publisher.publish(&exchange.name, archive_msg).await?;
+    println!("已发布 period {} 到 {}", summary.period, exchange.stream_key);
+    Ok(())
+}
+
+fn validate_config(cfg_path: &str) -> Result<()> {
+    let config = RedisConfig::from_file(cfg_path)?;
+    println!("配置有效: {}", cfg_path);
+    println!("  mode: {:?}", config.redis_pubber.mode);
+    println!(
+        "  redis: {}:{} ({:?}, tls: {})",
+        config.redis_pubber.host,
+        config.redis_pubber.port,
+        config.redis_pubber.topology,
+        config.redis_pubber.tls.as_ref().is_some_and(|t| t.enabled)
+    );
+    println!("  ha: enabled={}, is_primary={}", config.ha.enabled, config.is_primary);
+    println!("  script_sha: {}", mkt_pubber::push_msg_sha());
+    for exchange in config.exchanges() {
+        println!("  exchange: {}", exchange.name);
+        println!("    stream_key: {}", exchange.stream_key);
+        println!("    topic: {}", exchange.topic);
+        println!("    max_stream_size: {}", exchange.max_stream_size);
+        println!("    codec: {}", exchange.codec.as_str());
+        println!(
+            "    compression: {}",
+            exchange.compression.map_or("原样发布", |c| c.as_str())
+        );
+        println!("    zmq endpoints: {:?}", exchange.zmq.endpoints());
+    }
+    Ok(())
+}
+
+// `log_level_from_cli` 为 true 时命令行指定的日志级别优先, 热加载不覆盖
+async fn run(cfg_path: &str, log_level_from_cli: bool) -> Result<()> {
+    // 尽早注册 SIGHUP, 避免默认行为直接终止进程
+    let mut sighup = signal::unix::signal(signal::unix::SignalKind::hangup())?;
     println!("正在创建 Redis 发布者...");
-    let publisher = match RedisStreamMktPubber::new("./mkt_cfg.yaml").await {
+    let publisher = match RedisStreamMktPubber::new(cfg_path).await {
         Ok(p) => {
             println!("Redis 发布者创建成功");
-            p
+            Arc::new(p)
         },
         Err(e) => {
             println!("创建 Redis 发布者失败: {}", e);
             return Err(e);
         }
     };
-    // 创建接收器
+    let exchanges = publisher.config().exchanges();
+
+    // 接入地址相同的交易所共用一个接收器, 按 topic 分发到各自的发布队列;
+    // 接收器按第一个交易所的 zmq 配置创建, 配置校验保证 hwm 和 dual_feed 一致
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
-    let mut receiver = match ZmqReceiver::new(shutdown_rx) {
-        Ok(r) => r,
+    let mut receivers: Vec<(Vec<String>, ZmqReceiver)> = Vec::new();
+    let mut queues = Vec::new();
+    for exchange in &exchanges {
+        if exchange.zmq.overflow == OverflowPolicy::Block && !publisher.config().sequencer.enabled {
+            warn!(
+                "[{}] overflow 为 block 但未开启 sequencer, 超过 ZMQ 水位线被丢弃的 period 不会被发现",
+                exchange.name
+            );
+        }
+        let (msg_tx, msg_rx) = period_queue(&exchange.name, exchange.zmq.queue_size, exchange.zmq.overflow);
+        queues.push(msg_rx);
+        let endpoints = exchange.zmq.endpoints();
+        let receiver = match receivers.iter_mut().find(|(e, _)| *e == endpoints) {
+            Some((_, receiver)) => {
+                receiver.add_subscriptions(&exchange.zmq.topics)?;
+                receiver
+            }
+            None => match ZmqReceiver::new(&exchange.zmq, shutdown_rx.clone()) {
+                Ok(r) => {
+                    receivers.push((endpoints, r));
+                    &mut receivers.last_mut().unwrap().1
+                }
+                Err(e) => {
+                    println!("[{}] 创建接收器失败: {}", exchange.name, e);
+                    return Err(e);
+                }
+            },
+        };
+        receiver.add_route(&exchange.topic, msg_tx);
+    }
+
+    //使用tokio的canceltoken检测关闭信号
+    let token = CancellationToken::new();
+
+    // 主备模式下先竞选, 只有主实例会真正写入 Stream
+    // 租约使用单独的关闭信号, 发布管道退出前的最后一批 period 仍以主的身份写入
+    let lease_token = CancellationToken::new();
+    let leases = match publisher.start_leader_election(lease_token.clone()).await {
+        Ok(handles) => handles,
         Err(e) => {
-            println!("创建接收器失败: {}", e);
-            return Err(e.into());
+            error!("主备竞选启动失败: {}", e);
+            Vec::new()
         }
     };
-    
-    // 获取消息接收通道
-    let mut msg_rx = receiver.get_msg_rx();
-    
-    // 在后台任务中启动接收器
-    tokio::spawn(async move {
-        tokio::task::spawn_blocking(move || {
-            receiver.start_receiving();
-        }).await.expect("接收器任务失败");
-    });
-        // 主循环等待关闭信号
+
+    // 接收器已连接但尚未开始收取, 补数据期间的实时消息由ZMQ缓存
+    match publisher.config().redis_pubber.mode {
+        Mode::FromStart => {
+            info!("FromStart 模式, 先从归档补数据再切换到实时行情");
+            for exchange in &exchanges {
+                if let Err(e) = backfill_from_archive(&publisher, exchange).await {
+                    error!("[{}] 归档补数据失败: {}", exchange.name, e);
+                }
+            }
+        }
+        Mode::FromCurrent => {
+            info!("FromCurrent 模式, 跳过历史数据, 只发布新的 period");
+        }
+        _ => {}
+    }
+
+    // 在后台任务中启动接收器和各交易所的发布管道
+    let mut pipelines = Vec::new();
+    for (_, mut receiver) in receivers {
+        tokio::spawn(async move {
+            receiver.start_receiving().await;
+        });
+    }
+    let watchdog_cfg = publisher.config().watchdog.clone();
+    let mut watchdogs = Vec::new();
+    for (exchange, msg_rx) in exchanges.iter().zip(queues) {
+        let watchdog = watchdog_cfg
+            .enabled
+            .then(|| Arc::new(FeedWatchdog::new(&exchange.name, &watchdog_cfg)));
+        watchdogs.extend(watchdog.clone());
+        pipelines.push(tokio::spawn(run_pipeline(
+            publisher.clone(),
+            exchange.name.clone(),
+            msg_rx,
+            watchdog,
+            token.clone(),
+        )));
+    }
+
+    // 上游断流检测和健康检查接口
+    if watchdog_cfg.enabled {
+        if let Some(addr) = watchdog_cfg.health_addr.clone() {
+            let watchdogs = watchdogs.clone();
+            let token = token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_health(&addr, watchdogs, token).await {
+                    error!("健康检查服务启动失败: {}", e);
+                }
+            });
+        }
+        tokio::spawn(run_watchdog(publisher.clone(), watchdogs, watchdog_cfg, token.clone()));
+    }
+
     // 等待 SIGINT (Ctrl+C) 或 SIGTERM
-    //使用tokio的canceltoken检测关闭信号
-    let token = CancellationToken::new();
     let token_for_ctrl_c = token.clone();
     let token_for_sigterm = token.clone();
         // 创建一个tokio的task，用于检测ctrl_c信号
@@ -68,60 +283,41 @@ async fn main() -> Result<()> {
             // 只发送关闭信号给主循环，让主循环来处理接收器的关闭
             token_for_sigterm.cancel();
         });
-        
-    
+
+    // 主循环等待关闭信号, 并处理 SIGHUP 热加载
     loop {
         select! {
-            // 优先检查取消信号
             _ = token.cancelled() => {
                 info!("收到关闭信号，开始退出...");
                 break;
             }
-            
-            msg = msg_rx.recv() => {
-                let msg = match msg {
-                    Ok(m) => m,
-                    Err(e) => {
-                        if token.is_cancelled() {
-                            info!("程序正在关闭，停止处理消息");
-                            break;
-                        }
-                        println!("接收消息失败: {}", e);
-                        // 如果channel关闭且不是因为程序退出，则退出循环
-                        if e.to_string().contains("closed") {
-                            error!("消息通道已关闭，退出主循环");
-                            break;
+
+            _ = sighup.recv() => {
+                info!("SIGHUP received, 重新加载配置: {}", cfg_path);
+                match publisher.reload().await {
+                    Ok(changes) => {
+                        if changes.iter().any(|c| c.starts_with("log_level")) {
+                            if log_level_from_cli {
+                                info!("日志级别由命令行 --log-level 指定, 忽略配置文件中的 log_level");
+                            } else if let Some(level) = &publisher.config().log_level {
+                                logging::set_filter(level);
+                            }
                         }
-                        continue;
-                    }
-                };
-                
-                let message = match PeriodMessage::from_capnp(&msg, true) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        println!("解析消息失败: {}", e);
-                        continue;
                     }
-                };
-                
-                message.print_info();
-                let archive_msg = MktArchiveMsg::new(
-                    message.period,
-                    message.post_ts,
-                    message.total_info_count(),
-                    msg
-                );
-                
-                if let Err(e) = publisher.publish(archive_msg).await {
-                    println!("发布消息失败: {}", e);
-                } else {
-                    println!("发布消息成功");
+                    Err(e) => error!("热加载配置失败, 保持原配置: {}", e),
                 }
             }
         }
     }
     
     // 优雅关闭
+    for pipeline in pipelines {
+        let _ = pipeline.await;
+    }
+    lease_token.cancel();
+    for lease in leases {
+        let _ = lease.await;
+    }
     info!("发送关闭信号给ZMQ接收器...");
     let _ = shutdown_tx.send(true);
     
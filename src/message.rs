@@ -6,19 +6,131 @@ use capnp::serialize;
 //for protobuf
 use crate::proto::message_old;
 use prost::Message;
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use std::io::{Read, Write};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::Write;
 
 // 引用生成的 Cap'n Proto 代码
+use crate::compression::{decompress_auto, Compression};
 use crate::period_capnp;
+use crate::view::{DecodeError, PeriodView};
 
+/// 成交方向
+///
+/// 线上格式中仍为字符串; 解析时只接受 "B"/"S" 和 "buy"/"sell" (不区分大小写),
+/// 写出时统一为 "B"/"S"。无法识别的取值原样保留在 `Unknown` 中, 写出时不变。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum Side {
+    Buy,
+    Sell,
+    Unknown(String),
+}
+
+impl Side {
+    pub fn parse(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "b" | "buy" => Side::Buy,
+            "s" | "sell" => Side::Sell,
+            _ => Side::Unknown(s.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Side::Buy => "B",
+            Side::Sell => "S",
+            Side::Unknown(s) => s,
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Side::Unknown(_))
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<String> for Side {
+    fn from(s: String) -> Self {
+        Side::parse(&s)
+    }
+}
+
+impl From<Side> for String {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Unknown(s) => s,
+            known => known.as_str().to_string(),
+        }
+    }
+}
+
+/// 增量中一个档位的含义: 数量为 0 表示删除该价格的档位, 否则把该价格的数量设为 `amount`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LevelAction {
+    Set(f64),
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PriceLevel {
     pub price: f64,
     pub amount: f64,
 }
 
+impl PriceLevel {
+    pub fn is_delete(&self) -> bool {
+        self.amount == 0.0
+    }
+
+    pub fn action(&self) -> LevelAction {
+        if self.is_delete() {
+            LevelAction::Delete
+        } else {
+            LevelAction::Set(self.amount)
+        }
+    }
+}
+
+/// 按 `is_snapshot` 区分的盘口更新
+///
+/// `Snapshot` 替换整个盘口; `Delta` 只修改列出的档位, 见 [`PriceLevel::action`]。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookUpdate<'a> {
+    Snapshot {
+        bids: &'a [PriceLevel],
+        asks: &'a [PriceLevel],
+    },
+    Delta {
+        bids: &'a [PriceLevel],
+        asks: &'a [PriceLevel],
+    },
+}
+
+impl<'a> BookUpdate<'a> {
+    pub fn is_snapshot(&self) -> bool {
+        matches!(self, BookUpdate::Snapshot { .. })
+    }
+
+    pub fn bids(&self) -> &'a [PriceLevel] {
+        match self {
+            BookUpdate::Snapshot { bids, .. } | BookUpdate::Delta { bids, .. } => bids,
+        }
+    }
+
+    pub fn asks(&self) -> &'a [PriceLevel] {
+        match self {
+            BookUpdate::Snapshot { asks, .. } | BookUpdate::Delta { asks, .. } => asks,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IncrementOrderBookInfo {
     pub timestamp: i64,
     pub is_snapshot: bool,
@@ -26,19 +138,32 @@ pub struct IncrementOrderBookInfo {
     pub asks: Vec<PriceLevel>,
 }
 
+impl IncrementOrderBookInfo {
+    pub fn update(&self) -> BookUpdate<'_> {
+        if self.is_snapshot {
+            BookUpdate::Snapshot { bids: &self.bids, asks: &self.asks }
+        } else {
+            BookUpdate::Delta { bids: &self.bids, asks: &self.asks }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TradeInfo {
     pub timestamp: i64,
-    pub side: String,
+    pub side: Side,
     pub price: f64,
     pub amount: f64,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SymbolInfo {
     pub symbol: String,
     pub trades: Vec<TradeInfo>,
     pub incs: Vec<IncrementOrderBookInfo>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PeriodMessage {
     pub period: i64,
     pub ts: i64,
@@ -47,24 +172,46 @@ pub struct PeriodMessage {
     pub symbol_infos: Vec<SymbolInfo>,
 }
 
+// NDJSON 导出的一行, type 字段区分 trade 和 inc
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NdjsonLine<'a> {
+    Trade {
+        period: i64,
+        symbol: &'a str,
+        #[serde(flatten)]
+        trade: &'a TradeInfo,
+    },
+    Inc {
+        period: i64,
+        symbol: &'a str,
+        #[serde(flatten)]
+        inc: &'a IncrementOrderBookInfo,
+    },
+}
+
 impl PeriodMessage {
+    /// 只读取 period 字段, 用于日志等不需要完整解码的场合
+    pub fn peek_period(data: &[u8], is_compressed: bool) -> Result<i64> {
+        PeriodView::new(data, is_compressed)?.period()
+    }
+
+    /// 解码为完整的 PeriodMessage; 只需要头部和条数时使用 [`PeriodView`]
+    ///
+    /// `is_compressed` 为 true 时按魔数识别 zlib, gzip, zstd 或 lz4, 未压缩的数据也能读取。
     pub fn from_capnp(data: &[u8], is_compressed: bool) -> Result<Self> {
-        let data = if is_compressed {
-            let mut decoder = ZlibDecoder::new(data);
-            let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed)?;
-            decompressed
-        } else {
-            data.to_vec()
-        };
+        Self::from_capnp_with_options(data, is_compressed, ReaderOptions::new())
+    }
 
-        let message_reader = serialize::read_message(
-            &mut std::io::Cursor::new(data),
-            ReaderOptions::new(),
-        )?;
+    /// 超过 `options` 的限制时返回 [`DecodeError::TooLarge`]
+    pub fn from_capnp_with_options(data: &[u8], is_compressed: bool, options: ReaderOptions) -> Result<Self> {
+        let view = PeriodView::with_options(data, is_compressed, options)?;
+        view.root()
+            .and_then(Self::from_capnp_reader)
+            .map_err(DecodeError::classify)
+    }
 
-        let reader = message_reader.get_root::<period_capnp::period_message::Reader>()?;
-        
+    pub fn from_capnp_reader(reader: period_capnp::period_message::Reader<'_>) -> Result<Self> {
         Ok(PeriodMessage {
             period: reader.get_period(),
             ts: reader.get_ts(),
@@ -80,7 +227,7 @@ impl PeriodMessage {
                             .map(|trade| -> Result<TradeInfo> {
                                 Ok(TradeInfo {
                                     timestamp: trade.get_timestamp(),
-                                    side: trade.get_side()?.to_string()?,
+                                    side: Side::parse(trade.get_side()?.to_str()?),
                                     price: trade.get_price(),
                                     amount: trade.get_amount(),
                                 })
@@ -119,7 +266,19 @@ impl PeriodMessage {
         })
     }
 
+    /// `use_compression` 为 true 时使用默认级别的 zlib
     pub fn to_capnp(&self, use_compression: bool) -> Result<Vec<u8>> {
+        self.to_capnp_with(if use_compression { Compression::default() } else { Compression::None })
+    }
+
+    pub fn to_capnp_with(&self, compression: Compression) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        serialize::write_message(&mut buffer, &self.to_capnp_builder())?;
+        compression.compress(&buffer)
+    }
+
+    /// 构造 capnp 消息, 由调用方选择序列化方式
+    pub fn to_capnp_builder(&self) -> capnp::message::Builder<capnp::message::HeapAllocator> {
         let mut message = capnp::message::Builder::new_default();
         let mut builder = message.init_root::<period_capnp::period_message::Builder>();
         
@@ -138,7 +297,7 @@ impl PeriodMessage {
                 for (j, trade) in info.trades.iter().enumerate() {
                     let mut trade_builder = trades.reborrow().get(j as u32);
                     trade_builder.set_timestamp(trade.timestamp);
-                    trade_builder.set_side(&trade.side);
+                    trade_builder.set_side(trade.side.as_str());
                     trade_builder.set_price(trade.price);
                     trade_builder.set_amount(trade.amount);
                 }
@@ -174,30 +333,17 @@ impl PeriodMessage {
             }
         }
         
-        let mut buffer = Vec::new();
-        serialize::write_message(&mut buffer, &message)?;
-        
-        if use_compression {
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(&buffer)?;
-            let compressed = encoder.finish()?;
-            Ok(compressed)
-        } else {
-            Ok(buffer)
-        }
+        message
     }
 
     pub fn from_protobuf(data: &[u8], is_compressed: bool) -> Result<Self> {
         let data = if is_compressed {
-            let mut decoder = ZlibDecoder::new(data);
-            let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed)?;
-            decompressed
+            decompress_auto(data)?
         } else {
-            data.to_vec()
+            Cow::Borrowed(data)
         };
 
-        let proto_msg = message_old::PeriodMessage::decode(data.as_slice())?;
+        let proto_msg = message_old::PeriodMessage::decode(data.as_ref())?;
         
         Ok(PeriodMessage {
             period: proto_msg.period,
@@ -208,7 +354,7 @@ impl PeriodMessage {
                 symbol: info.symbol,
                 trades: info.trades.into_iter().map(|trade| TradeInfo {
                     timestamp: trade.timestamp,
-                    side: trade.side,
+                    side: Side::from(trade.side),
                     price: trade.price,
                     amount: trade.amount,
                 }).collect(),
@@ -228,7 +374,12 @@ impl PeriodMessage {
         })
     }
 
+    /// `use_compression` 为 true 时使用默认级别的 zlib
     pub fn to_protobuf(&self, use_compression: bool) -> Result<Vec<u8>> {
+        self.to_protobuf_with(if use_compression { Compression::default() } else { Compression::None })
+    }
+
+    pub fn to_protobuf_with(&self, compression: Compression) -> Result<Vec<u8>> {
         let proto_msg = message_old::PeriodMessage {
             period: self.period,
             ts: self.ts,
@@ -238,7 +389,7 @@ impl PeriodMessage {
                 symbol: info.symbol.clone(),
                 trades: info.trades.iter().map(|trade| message_old::TradeInfo {
                     timestamp: trade.timestamp,
-                    side: trade.side.clone(),
+                    side: trade.side.as_str().to_string(),
                     price: trade.price,
                     amount: trade.amount,
                 }).collect(),
@@ -260,14 +411,38 @@ impl PeriodMessage {
         let mut buffer = Vec::new();
         proto_msg.encode(&mut buffer)?;
         
-        if use_compression {
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(&buffer)?;
-            let compressed = encoder.finish()?;
-            Ok(compressed)
-        } else {
-            Ok(buffer)
+        compression.compress(&buffer)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn to_json_pretty(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self> {
+        Ok(serde_json::from_str(data)?)
+    }
+
+    /// 按 NDJSON 输出, 每行一条 trade 或 inc, 带上 period 和 symbol, 便于用 jq 处理
+    ///
+    /// 每个 symbol 先输出全部 trade 再输出全部 inc, 各自保持原有顺序
+    pub fn write_ndjson<W: Write>(&self, mut writer: W) -> Result<()> {
+        for info in &self.symbol_infos {
+            for trade in &info.trades {
+                let line = NdjsonLine::Trade { period: self.period, symbol: &info.symbol, trade };
+                serde_json::to_writer(&mut writer, &line)?;
+                writer.write_all(b"\n")?;
+            }
+            for inc in &info.incs {
+                let line = NdjsonLine::Inc { period: self.period, symbol: &info.symbol, inc };
+                serde_json::to_writer(&mut writer, &line)?;
+                writer.write_all(b"\n")?;
+            }
         }
+        Ok(())
     }
 
     pub fn print_info(&self) {
@@ -305,4 +480,54 @@ impl PeriodMessage {
         info!("Total Trade Info Count: {}", trade_count);
         inc_count + trade_count
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_parse_accepts_only_buy_and_sell() {
+        for s in ["B", "b", "buy", "BUY", "Buy"] {
+            assert_eq!(Side::parse(s), Side::Buy, "{}", s);
+        }
+        for s in ["S", "s", "sell", "SELL", "Sell"] {
+            assert_eq!(Side::parse(s), Side::Sell, "{}", s);
+        }
+        for s in ["bid", "ask", "offer", "", "x", "bu"] {
+            assert_eq!(Side::parse(s), Side::Unknown(s.to_string()), "{}", s);
+        }
+    }
+
+    #[test]
+    fn side_serializes_as_b_and_s() {
+        assert_eq!(String::from(Side::parse("buy")), "B");
+        assert_eq!(String::from(Side::parse("Sell")), "S");
+        assert_eq!(Side::parse("bid").to_string(), "bid");
+
+        assert_eq!(serde_json::to_string(&Side::parse("BUY")).unwrap(), "\"B\"");
+        assert_eq!(serde_json::to_string(&Side::parse("s")).unwrap(), "\"S\"");
+        let side: Side = serde_json::from_str("\"sell\"").unwrap();
+        assert_eq!(side, Side::Sell);
+    }
+
+    #[test]
+    fn side_round_trips_through_capnp() {
+        let trade = |side: &str| TradeInfo { timestamp: 3, side: Side::parse(side), price: 1.0, amount: 2.0 };
+        let msg = PeriodMessage {
+            period: 1,
+            ts: 2,
+            post_ts: 3,
+            poster_id: "p".to_string(),
+            symbol_infos: vec![SymbolInfo {
+                symbol: "BTC".to_string(),
+                trades: vec![trade("buy"), trade("S"), trade("bid")],
+                incs: vec![],
+            }],
+        };
+        let data = msg.to_capnp(false).unwrap();
+        let decoded = PeriodMessage::from_capnp(&data, false).unwrap();
+        let sides: Vec<_> = decoded.symbol_infos[0].trades.iter().map(|t| t.side.as_str().to_string()).collect();
+        assert_eq!(sides, ["B", "S", "bid"]);
+    }
+}
@@ -0,0 +1,347 @@
+// 单个交易所的处理管道: 接收 -> 解码 -> 仲裁/延迟择优 -> 排序 -> 组装 -> 发布
+use bytes::Bytes;
+use log::{debug, info, warn};
+use std::sync::Arc;
+use tokio::select;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::arbiter::PeriodArbiter;
+use crate::codec::normalize_to_capnp;
+use crate::config::ArbiterConfig;
+use crate::queue::QueueReceiver;
+use crate::sequencer::{PeriodSequencer, Sequenced};
+use crate::stats::{Bar, StatsEngine};
+use crate::validate::ViolationCounter;
+use crate::watchdog::FeedWatchdog;
+use crate::view::{summarize, DecodeError, PeriodSummary};
+use crate::{MktArchiveMsg, PeriodMessage, RedisStreamMktPubber};
+
+// 双路行情时的行情源数量
+const DUAL_FEED_COUNT: usize = 2;
+
+// 解码出头部和条数后等待发布的一个 period
+type Decoded = (PeriodSummary, Bytes);
+
+/// 持续消费接收器的消息并发布到该交易所的 Stream, 直到收到关闭信号或队列关闭
+///
+/// 交易所开启 `dual_feed` 时, 同一 period 的多份副本先经过仲裁, 只发布一次;
+/// 开启 `hold_back` 时每个 period 等待 `delay_ms` 后只发布 info_count 最大的版本;
+/// 开启 `sequencer` 时按 period 顺序发布, 超时仍缺失的 period 写入 GAP 条目。
+/// 交易所开启 `detect_codec` 时收到的 protobuf 等格式先转为 capnp。
+/// 每条解码成功的消息都会报告给 `watchdog`。开启 `validation` 时按 symbol
+/// 统计数据质量违规, 达到 `reject_severity` 的 period 不发布。开启 `stats` 时
+/// 每个发布的 period 计算行情统计和 K 线, 写入统计 Stream。
+pub async fn run_pipeline(
+    publisher: Arc<RedisStreamMktPubber>,
+    exchange: String,
+    mut msg_rx: QueueReceiver,
+    watchdog: Option<Arc<FeedWatchdog>>,
+    shutdown: CancellationToken,
+) {
+    info!("[{}] 发布管道启动", exchange);
+    let config = publisher.config();
+    let mut arbiter = config.find_exchange(&exchange).and_then(|e| {
+        if config.hold_back.enabled {
+            // 不论收到几份都等满 delay_ms, 只发布最终版本
+            let cfg = ArbiterConfig {
+                window_ms: config.hold_back.delay_ms,
+                ..e.zmq.arbiter.clone()
+            };
+            Some(PeriodArbiter::new(&exchange, None, &cfg))
+        } else if e.zmq.dual_feed {
+            Some(PeriodArbiter::new(&exchange, Some(DUAL_FEED_COUNT), &e.zmq.arbiter))
+        } else {
+            None
+        }
+    });
+    let mut sequencer = config
+        .sequencer
+        .enabled
+        .then(|| PeriodSequencer::<Decoded>::new(&exchange, &config.sequencer));
+    let mut stats = config.stats.enabled.then(|| StatsEngine::new(&config.stats.bars));
+    let detect_codec = config.find_exchange(&exchange).is_some_and(|e| e.zmq.detect_codec);
+    drop(config);
+    let mut violations = ViolationCounter::default();
+
+    loop {
+        let deadline = [
+            arbiter.as_ref().and_then(|a| a.next_deadline()),
+            sequencer.as_ref().and_then(|s| s.next_deadline()),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        select! {
+            // 优先检查取消信号
+            _ = shutdown.cancelled() => {
+                info!("[{}] 收到关闭信号, 发布管道退出", exchange);
+                break;
+            }
+
+            _ = sleep_until(deadline), if deadline.is_some() => {
+                let now = Instant::now().into_std();
+                let mut ready = Vec::new();
+                if let Some(arbiter) = arbiter.as_mut() {
+                    for period in arbiter.take_expired(now) {
+                        ready.extend(sequence(&mut sequencer, (period.summary, period.raw)));
+                    }
+                }
+                if let Some(sequencer) = sequencer.as_mut() {
+                    ready.extend(sequencer.on_timeout(now));
+                }
+                publish_all(&publisher, &exchange, &mut stats, ready).await;
+            }
+
+            msg = msg_rx.recv() => {
+                let Some(msg) = msg else {
+                    // 接收器已退出且队列已取空
+                    info!("[{}] 消息队列已关闭，退出发布管道", exchange);
+                    break;
+                };
+
+                // 上游可能混有 protobuf 等格式, 统一转为 capnp; 解压后的数据留给下面解析, 不再解压
+                let (msg, plain) = if detect_codec {
+                    match normalize_to_capnp(msg, &publisher.config().decode) {
+                        Ok(m) => (m.raw, Some(m.plain)),
+                        Err(e) => {
+                            println!("[{}] 识别消息格式失败: {}", exchange, e);
+                            continue;
+                        }
+                    }
+                } else {
+                    (msg, None)
+                };
+                let (data, is_compressed) = match &plain {
+                    Some(plain) => (plain, false),
+                    None => (&msg, true),
+                };
+
+                // 只读取头部和条数, 不构造完整的 PeriodMessage
+                let summary = match summarize(data, is_compressed, &publisher.config().decode, true) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        println!("[{}] 解析消息失败: {}", exchange, e);
+                        continue;
+                    }
+                };
+
+                if let Some(watchdog) = &watchdog {
+                    watchdog.observe(summary.period);
+                }
+
+                if !passes_validation(&publisher, &exchange, data, is_compressed, &mut violations) {
+                    continue;
+                }
+
+                let decoded = match arbiter.as_mut() {
+                    Some(arbiter) => match arbiter.offer(summary, msg) {
+                        Some(period) => (period.summary, period.raw),
+                        None => continue,
+                    },
+                    None => (summary, msg),
+                };
+                let ready = sequence(&mut sequencer, decoded);
+                publish_all(&publisher, &exchange, &mut stats, ready).await;
+            }
+        }
+    }
+
+    // 仲裁窗口和重排窗口内尚未发布的 period 在退出前发布
+    let mut ready = Vec::new();
+    if let Some(arbiter) = arbiter.as_mut() {
+        for period in arbiter.take_all() {
+            ready.extend(sequence(&mut sequencer, (period.summary, period.raw)));
+        }
+        info!("[{}] 仲裁累计丢弃迟到副本: {}", exchange, arbiter.late_dropped());
+    }
+    if let Some(sequencer) = sequencer.as_mut() {
+        ready.extend(sequencer.take_all());
+        info!(
+            "[{}] 累计缺失 period: {}, 按范围标记跳过: {}",
+            exchange,
+            sequencer.missing().len(),
+            sequencer.range_skipped()
+        );
+    }
+    publish_all(&publisher, &exchange, &mut stats, ready).await;
+    // 最后一根 K 线尚未结束, 按已汇总的部分发布
+    if let Some(stats) = stats.as_mut() {
+        publish_bars(&publisher, &exchange, &stats.flush()).await;
+    }
+    if !violations.by_symbol().is_empty() {
+        info!(
+            "[{}] 累计数据质量违规: {:?}, 拒绝发布 period: {}",
+            exchange,
+            violations.by_symbol(),
+            violations.rejected_periods()
+        );
+    }
+}
+
+// 未开启检查时直接通过; 检查配置每条消息重新读取, 支持热加载
+fn passes_validation(
+    publisher: &RedisStreamMktPubber,
+    exchange: &str,
+    msg: &[u8],
+    is_compressed: bool,
+    violations: &mut ViolationCounter,
+) -> bool {
+    let cfg = publisher.config().validation.clone();
+    if !cfg.enabled {
+        return true;
+    }
+    let decode = publisher.config().decode.clone();
+    let report = match PeriodMessage::from_capnp_with_options(msg, is_compressed, decode.reader_options()) {
+        Ok(m) => m.validate_with_period_ms(cfg.period_ms),
+        Err(e) if decode.oversize_fallback && DecodeError::is_too_large(&e) => {
+            warn!("[{}] {}, 跳过数据质量检查", exchange, e);
+            return true;
+        }
+        Err(e) => {
+            println!("[{}] 解析消息失败: {}", exchange, e);
+            return false;
+        }
+    };
+    if report.is_clean() {
+        return true;
+    }
+    violations.record(&report);
+    warn!(
+        "[{}] period {} 数据质量违规 {} 条, 按 symbol: {:?}",
+        exchange,
+        report.period,
+        report.violations.len(),
+        report.count_by_symbol()
+    );
+    if let Some(first) = report.violations.first() {
+        warn!("[{}] 首条违规: {} {:?} {}", exchange, first.symbol, first.kind, first.detail);
+    }
+    match cfg.reject_severity {
+        Some(threshold) if report.fails(threshold) => {
+            violations.record_rejected();
+            warn!(
+                "[{}] period {} 违规达到 {:?}, 不发布",
+                exchange, report.period, threshold
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
+// 未开启排序时原样输出
+fn sequence(sequencer: &mut Option<PeriodSequencer<Decoded>>, decoded: Decoded) -> Vec<Sequenced<Decoded>> {
+    match sequencer.as_mut() {
+        Some(sequencer) => sequencer.push(decoded.0.period, decoded),
+        None => vec![Sequenced::Period(decoded.0.period, decoded)],
+    }
+}
+
+async fn sleep_until(deadline: Option<std::time::Instant>) {
+    if let Some(deadline) = deadline {
+        tokio::time::sleep_until(Instant::from_std(deadline)).await;
+    }
+}
+
+async fn publish_all(
+    publisher: &RedisStreamMktPubber,
+    exchange: &str,
+    stats: &mut Option<StatsEngine>,
+    ready: Vec<Sequenced<Decoded>>,
+) {
+    for item in ready {
+        match item {
+            Sequenced::Period(_, (summary, raw)) => {
+                if let Some(stats) = stats.as_mut() {
+                    publish_stats(publisher, exchange, stats, &raw).await;
+                }
+                publish_period(publisher, exchange, summary, raw).await
+            }
+            Sequenced::Gap(period) => publish_gap(publisher, exchange, period, period).await,
+            Sequenced::GapRange(first, last) => publish_gap(publisher, exchange, first, last).await,
+        }
+    }
+}
+
+// 统计只用于看板, 失败时只打印, 不影响行情发布
+async fn publish_stats(publisher: &RedisStreamMktPubber, exchange: &str, stats: &mut StatsEngine, raw: &[u8]) {
+    let options = publisher.config().decode.reader_options();
+    let msg = match PeriodMessage::from_capnp_with_options(raw, true, options) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("[{}] 解析消息失败, 跳过统计: {}", exchange, e);
+            return;
+        }
+    };
+    let Some((period_stats, bars)) = stats.process(&msg) else {
+        return;
+    };
+    if publisher.config().stats.publish_periods {
+        let data = serde_json::to_string(&period_stats);
+        publish_stat(publisher, exchange, "period", period_stats.ts, data).await;
+    }
+    publish_bars(publisher, exchange, &bars).await;
+}
+
+// 同一周期的 K 线合成一条
+async fn publish_bars(publisher: &RedisStreamMktPubber, exchange: &str, bars: &[Bar]) {
+    for same in bars.chunk_by(|a, b| a.interval == b.interval) {
+        let data = serde_json::to_string(same);
+        publish_stat(publisher, exchange, same[0].interval.as_str(), same[0].start_ts, data).await;
+    }
+}
+
+async fn publish_stat(
+    publisher: &RedisStreamMktPubber,
+    exchange: &str,
+    kind: &str,
+    ts: i64,
+    data: serde_json::Result<String>,
+) {
+    let result = match data {
+        Ok(data) => publisher.publish_stats(exchange, kind, ts, &data).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        warn!("[{}] 发布 {} 统计失败: {}", exchange, kind, e);
+    }
+}
+
+// 单个缺失 period 时 first 与 last 相同, 写 GAP 条目; 否则写一条范围标记
+async fn publish_gap(publisher: &RedisStreamMktPubber, exchange: &str, first: i64, last: i64) {
+    let codec = match publisher.config().find_exchange(exchange) {
+        Some(e) => e.codec,
+        None => return,
+    };
+    let (msg, periods) = if first == last {
+        (MktArchiveMsg::gap(first, codec), first.to_string())
+    } else {
+        (MktArchiveMsg::gap_range(first, last, codec), format!("{}..{}", first, last))
+    };
+    if let Err(e) = publisher.publish(exchange, msg).await {
+        println!("[{}] 发布缺口标记失败, period {}: {}", exchange, periods, e);
+    } else if publisher.is_leader(exchange) {
+        println!("[{}] 发布缺口标记成功, period {}", exchange, periods);
+    } else {
+        debug!("[{}] [备] 未写入缺口标记, period {}", exchange, periods);
+    }
+}
+
+async fn publish_period(publisher: &RedisStreamMktPubber, exchange: &str, summary: PeriodSummary, raw: Bytes) {
+    let archive_msg = match publisher.build_archive_msg(exchange, &summary, raw.to_vec()) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("[{}] 转码消息失败: {}", exchange, e);
+            return;
+        }
+    };
+
+    if let Err(e) = publisher.publish(exchange, archive_msg).await {
+        println!("[{}] 发布消息失败: {}", exchange, e);
+    } else if publisher.is_leader(exchange) {
+        println!("[{}] 发布消息成功", exchange);
+    } else {
+        debug!("[{}] [备] 未写入 period {}", exchange, summary.period);
+    }
+}
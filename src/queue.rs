@@ -0,0 +1,187 @@
+// 接收器与发布管道之间的有界队列, 队列满时按配置的策略处理
+use bytes::Bytes;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::codec::{looks_like_capnp, split_header, CodecKind};
+use crate::config::OverflowPolicy;
+use crate::PeriodMessage;
+
+struct Shared {
+    name: String,
+    capacity: usize,
+    policy: OverflowPolicy,
+    queue: Mutex<State>,
+    // 队列由空变为非空, 或发送端关闭
+    not_empty: Notify,
+    // 队列腾出空位, 或接收端关闭
+    not_full: Notify,
+    dropped: AtomicU64,
+}
+
+struct State {
+    items: VecDeque<Bytes>,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+/// 创建一个容量为 `capacity` 的队列, `name` 用于日志 (一般为交易所名)
+///
+/// 两端各只有一个持有者: 发送端在接收器里, 接收端在发布管道里。
+pub fn period_queue(name: &str, capacity: usize, policy: OverflowPolicy) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        name: name.to_string(),
+        capacity: capacity.max(1),
+        policy,
+        queue: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity.max(1)),
+            sender_closed: false,
+            receiver_closed: false,
+        }),
+        not_empty: Notify::new(),
+        not_full: Notify::new(),
+        dropped: AtomicU64::new(0),
+    });
+    (QueueSender { shared: shared.clone() }, QueueReceiver { shared })
+}
+
+pub struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+impl QueueSender {
+    /// 放入一条消息
+    ///
+    /// `Block` 策略下队列满时一直等待到有空位, 期间接收器不再从 ZMQ 收取,
+    /// 消息堆积在 ZMQ 的接收缓冲中; SUB socket 超过接收水位线后静默丢弃新消息,
+    /// 不会向上游施加背压。
+    /// 接收端已关闭时返回 false。
+    pub async fn send(&self, msg: Bytes) -> bool {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut state = shared.queue.lock().unwrap();
+                if state.receiver_closed {
+                    return false;
+                }
+                if state.items.len() < shared.capacity {
+                    state.items.push_back(msg);
+                    drop(state);
+                    shared.not_empty.notify_one();
+                    return true;
+                }
+                match shared.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        let oldest = state.items.pop_front();
+                        state.items.push_back(msg);
+                        drop(state);
+                        if let Some(oldest) = oldest {
+                            shared.record_drop(&oldest, "丢弃最旧消息");
+                        }
+                        shared.not_empty.notify_one();
+                        return true;
+                    }
+                    OverflowPolicy::DropNewest => {
+                        drop(state);
+                        shared.record_drop(&msg, "丢弃最新消息");
+                        return true;
+                    }
+                }
+            }
+            shared.not_full.notified().await;
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().sender_closed = true;
+        self.shared.not_empty.notify_one();
+    }
+}
+
+pub struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+impl QueueReceiver {
+    /// 取出一条消息, 队列为空时等待; 发送端关闭且队列已取空时返回 None
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        let shared = &self.shared;
+        loop {
+            {
+                let mut state = shared.queue.lock().unwrap();
+                if let Some(msg) = state.items.pop_front() {
+                    drop(state);
+                    shared.not_full.notify_one();
+                    return Some(msg);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            shared.not_empty.notified().await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().receiver_closed = true;
+        self.shared.not_full.notify_one();
+        let dropped = self.dropped();
+        if dropped > 0 {
+            info!("[{}] 队列关闭, 累计丢弃 {} 条消息", self.shared.name, dropped);
+        }
+    }
+}
+
+impl Shared {
+    fn record_drop(&self, msg: &Bytes, reason: &str) {
+        let total = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        match cheap_period(msg) {
+            Some(period) => warn!(
+                "[{}] 队列已满(容量 {}), {}, period: {}, 累计丢弃: {}",
+                self.name, self.capacity, reason, period, total
+            ),
+            None => warn!(
+                "[{}] 队列已满(容量 {}), {}, 累计丢弃: {}",
+                self.name, self.capacity, reason, total
+            ),
+        }
+    }
+}
+
+// 队列已满时正是处理不过来的时候, 只对未压缩的标准 capnp 直接读取 period 字段;
+// 压缩, packed 或 protobuf 的消息需要解压或完整解码, 不读取 period
+fn cheap_period(msg: &[u8]) -> Option<i64> {
+    let payload = match split_header(msg) {
+        Some((CodecKind::Capnp, payload)) => payload,
+        Some(_) => return None,
+        None => msg,
+    };
+    if !looks_like_capnp(payload) {
+        return None;
+    }
+    PeriodMessage::peek_period(payload, false).ok()
+}
@@ -1,24 +1,84 @@
 //测试用的zmq receiver
 use zmq::{Context, Socket, SocketType};
 use log::{info, warn, error};
+use bytes::Bytes;
+use futures::Stream;
+use std::os::fd::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
 use std::time::Duration;
+use tokio::io::unix::AsyncFd;
 use tokio::sync::watch;
+
+use crate::config::ZmqProxyConfig;
+use crate::queue::QueueSender;
+
+// ZMQ_FD 是边沿触发的通知fd, 可读只表示 socket 状态可能变化,
+// 真正是否有消息需要再查 ZMQ_EVENTS
+struct ZmqFd(RawFd);
+
+impl AsRawFd for ZmqFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// 收到的一条消息, 单帧消息没有 topic
+#[derive(Debug, Clone)]
+pub struct ZmqMessage {
+    pub topic: Option<Bytes>,
+    pub payload: Bytes,
+}
+
+impl ZmqMessage {
+    fn from_frames(mut frames: Vec<Vec<u8>>) -> Option<Self> {
+        match frames.len() {
+            1 => Some(Self {
+                topic: None,
+                payload: Bytes::from(frames.pop()?),
+            }),
+            2 => {
+                let payload = Bytes::from(frames.pop()?);
+                let topic = Bytes::from(frames.pop()?);
+                Some(Self { topic: Some(topic), payload })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn topic_str(&self) -> Option<&str> {
+        self.topic.as_deref().map(|t| std::str::from_utf8(t).unwrap_or("<non-utf8>"))
+    }
+}
+
+// topic 到发布管道队列的映射
+struct Route {
+    topic: String,
+    msg_tx: QueueSender,
+}
+
 pub struct ZmqReceiver{
     #[allow(dead_code)]
     context: Context,
+    // 字段按声明顺序析构: 通知fd 必须先于 socket 从 reactor 注销, 否则 socket
+    // 关闭后 fd 号可能已被复用
+    ipc_fd: AsyncFd<ZmqFd>,
     ipc_socket: Socket,
+    endpoints: Vec<String>,
+    topics: Vec<String>,
     receive_count: u64,
     receiver_shutdown_rx: watch::Receiver<bool>,
-    pub msg_tx : tokio::sync::broadcast::Sender<Vec<u8>>,
+    // 第一个路由同时接收没有 topic 的单帧消息
+    routes: Vec<Route>,
+    unknown_topic_count: u64,
 }
 
 #[allow(dead_code)]
 impl ZmqReceiver {
-    pub fn get_msg_rx(&self) -> tokio::sync::broadcast::Receiver<Vec<u8>> {
-        self.msg_tx.subscribe()
-    }
-
-    pub fn new(receiver_shutdown_rx: watch::Receiver<bool>) -> Result<Self, zmq::Error> {
+    /// 需要在 tokio runtime 内调用, socket 的通知fd会注册到 reactor
+    ///
+    /// 创建后通过 [`ZmqReceiver::add_route`] 注册各交易所的发布队列。
+    pub fn new(cfg: &ZmqProxyConfig, receiver_shutdown_rx: watch::Receiver<bool>) -> anyhow::Result<Self> {
         // 创建ZMQ上下文
         let context = Context::new();
         context.set_io_threads(1)?;
@@ -27,75 +87,190 @@ impl ZmqReceiver {
         let ipc_socket = context.socket(SocketType::SUB)?;
         
         // 设置接收水位线
-        ipc_socket.set_rcvhwm(100 as i32)?;
-
-        let (msg_tx, _msg_rx) = tokio::sync::broadcast::channel(3);
+        ipc_socket.set_rcvhwm(cfg.hwm)?;
+        let ipc_fd = AsyncFd::new(ZmqFd(ipc_socket.get_fd()?))?;
         
         let mut receiver = Self {
             context,
             ipc_socket,
+            ipc_fd,
+            endpoints: cfg.endpoints(),
+            topics: cfg.topics.clone(),
             receive_count: 0,
-            receiver_shutdown_rx: receiver_shutdown_rx,
-            msg_tx: msg_tx,
+            receiver_shutdown_rx,
+            routes: Vec::new(),
+            unknown_topic_count: 0,
         };
         
         receiver.subscribe()?;
         Ok(receiver)
     }
     
-    fn subscribe(&mut self) -> Result<(), zmq::Error> {
-        let ipc_addr = format!("ipc://{}", "/tmp/mkt_archive.ipc");
-        // 订阅所有主题
-        self.ipc_socket.set_subscribe(b"")?;
-        match self.ipc_socket.connect(&ipc_addr) {
-            Ok(_) => {
-                info!("ZmqReceiver connect success, ipc: {}", ipc_addr);
-                Ok(())
+    /// 将 topic 为 `topic` 的消息放入 `msg_tx`; 第一个注册的路由同时接收单帧消息
+    pub fn add_route(&mut self, topic: &str, msg_tx: QueueSender) {
+        info!("ZmqReceiver route topic {:?} on {:?}", topic, self.endpoints);
+        self.routes.push(Route {
+            topic: topic.to_string(),
+            msg_tx,
+        });
+    }
+
+    /// 追加订阅前缀, 多个交易所共用接收器时订阅各自的 topic
+    pub fn add_subscriptions(&mut self, topics: &[String]) -> Result<(), zmq::Error> {
+        for topic in topics {
+            if !self.topics.contains(topic) {
+                self.ipc_socket.set_subscribe(topic.as_bytes())?;
+                self.topics.push(topic.clone());
             }
-            Err(e) => {
-                error!("ZmqReceiver IPC connect failed, path: {}, error: {}", ipc_addr, e);
-                Err(e)
+        }
+        Ok(())
+    }
+
+    fn subscribe(&mut self) -> Result<(), zmq::Error> {
+        for topic in &self.topics {
+            self.ipc_socket.set_subscribe(topic.as_bytes())?;
+        }
+        for endpoint in &self.endpoints {
+            match self.ipc_socket.connect(endpoint) {
+                Ok(_) => {
+                    info!("ZmqReceiver connect success, endpoint: {}", endpoint);
+                }
+                Err(e) => {
+                    error!("ZmqReceiver connect failed, endpoint: {}, error: {}", endpoint, e);
+                    return Err(e);
+                }
             }
         }
+        Ok(())
     }
     
-    pub fn start_receiving(&mut self) {
-        info!("ZmqReceiver started, listening on ipc://{}", "/tmp/mkt_archive.ipc");
-        
+    /// 异步接收一条完整消息(含全部帧), 没有消息时挂起直到 socket 可读, 可安全地在 select 中取消
+    pub async fn recv(&mut self) -> Result<ZmqMessage, zmq::Error> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    // 信号中断 (EINTR) 和暂无消息 (EAGAIN) 在这里重试, 不返回给调用方
+    fn poll_recv(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<ZmqMessage, zmq::Error>> {
+        loop {
+            let mut guard = match ready!(self.ipc_fd.poll_read_ready(cx)) {
+                Ok(guard) => guard,
+                Err(e) => {
+                    error!("ZmqReceiver poll fd failed: {}", e);
+                    return Poll::Ready(Err(zmq::Error::EINVAL));
+                }
+            };
+            // 读取 ZMQ_EVENTS 会处理 socket 内部的待办命令并重置通知fd
+            match self.ipc_socket.get_events() {
+                Ok(events) if events.contains(zmq::POLLIN) => {}
+                Ok(_) => {
+                    guard.clear_ready();
+                    continue;
+                }
+                Err(zmq::Error::EINTR) => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            // 多帧消息由 ZMQ 整体投递, 第一帧可读时其余帧也已到达
+            match self.ipc_socket.recv_multipart(zmq::DONTWAIT) {
+                Ok(frames) => {
+                    let frame_count = frames.len();
+                    match ZmqMessage::from_frames(frames) {
+                        Some(msg) => return Poll::Ready(Ok(msg)),
+                        None => warn!("丢弃帧数不支持的消息, 帧数: {}", frame_count),
+                    }
+                }
+                Err(zmq::Error::EAGAIN | zmq::Error::EINTR) => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    pub async fn start_receiving(&mut self) {
+        info!("ZmqReceiver started, listening on {:?}", self.endpoints);
+        let mut shutdown_rx = self.receiver_shutdown_rx.clone();
+
         loop {
             // 检查关闭信号
-            if *self.receiver_shutdown_rx.borrow() {
+            if *shutdown_rx.borrow() {
                 info!("ZmqReceiver received shutdown signal, stopping...");
                 break;
             }
-            
-            // 接收消息
-            match self.ipc_socket.recv_bytes(zmq::DONTWAIT) {
-                Ok(msg) => {
-                    self.receive_count += 1;
-                    self.process_message(&msg);
-                }
-                Err(e) => {
-                    if e == zmq::Error::EAGAIN {
-                        // 没有消息可接收，短暂睡眠后继续
-                        std::thread::sleep(Duration::from_millis(1));
-                    } else {
+
+            tokio::select! {
+                _ = shutdown_rx.changed() => continue,
+                msg = self.recv() => match msg {
+                    Ok(msg) => {
+                        self.receive_count += 1;
+                        if !self.process_message(msg).await {
+                            info!("发布管道已关闭, ZmqReceiver 停止接收");
+                            break;
+                        }
+                    }
+                    // context 已终止, 无法再接收
+                    Err(zmq::Error::ETERM) => {
+                        error!("ZMQ context terminated, ZmqReceiver 停止接收");
+                        break;
+                    }
+                    // 其他错误等待一秒后重试, 与原先的同步接收循环一致
+                    Err(e) => {
                         error!("Error receiving message: {}", e);
-                        std::thread::sleep(Duration::from_millis(1000));
+                        tokio::select! {
+                            _ = shutdown_rx.changed() => {}
+                            _ = tokio::time::sleep(Duration::from_millis(1000)) => {}
+                        }
                     }
-                }
+                },
             }
         }
         info!("ZmqReceiver stopped gracefully");
     }
-    
-    fn process_message(&self, msg: &[u8]) {
+
+    // 按 topic 放入对应队列, 队列满且策略为 Block 时在这里等待, 不再从 socket 收取,
+    // 超过接收水位线的消息由 ZMQ 丢弃;
+    // 所有发布管道都已关闭时返回 false
+    async fn process_message(&mut self, msg: ZmqMessage) -> bool {
         // 处理接收到的消息
-        info!("接收到消息，长度: {} 字节", msg.len());
-        
-        // 发送消息到broadcast channel
-        if let Err(e) = self.msg_tx.send(msg.to_vec()) {
-            warn!("发送消息到channel失败: {}", e);
+        info!(
+            "接收到消息，topic: {:?}, 长度: {} 字节",
+            msg.topic_str().unwrap_or(""),
+            msg.payload.len()
+        );
+
+        let index = match msg.topic_str() {
+            None => 0,
+            Some(topic) => match self.routes.iter().position(|r| r.topic == topic) {
+                Some(index) => index,
+                None => {
+                    self.unknown_topic_count += 1;
+                    warn!(
+                        "未知 topic {:?}, 丢弃消息, 累计: {}",
+                        topic, self.unknown_topic_count
+                    );
+                    return true;
+                }
+            },
+        };
+        let Some(route) = self.routes.get(index) else {
+            return false;
+        };
+        if !route.msg_tx.send(msg.payload).await {
+            info!("topic {:?} 的发布管道已关闭", route.topic);
+            self.routes.remove(index);
+        }
+        !self.routes.is_empty()
+    }
+}
+
+/// 逐条产出收到的消息内容(不含 topic), socket 出错(如 context 已终止)时结束
+impl Stream for ZmqReceiver {
+    type Item = Bytes;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Bytes>> {
+        match ready!(self.get_mut().poll_recv(cx)) {
+            Ok(msg) => Poll::Ready(Some(msg.payload)),
+            Err(e) => {
+                error!("Error receiving message: {}", e);
+                Poll::Ready(None)
+            }
         }
     }
 }
@@ -103,12 +278,12 @@ impl ZmqReceiver {
 impl Drop for ZmqReceiver {
     fn drop(&mut self) {
         info!("ZmqReceiver stopping...");
-        let ipc_addr = format!("ipc://{}", "/tmp/mkt_archive.ipc");
-        
-        if let Err(e) = self.ipc_socket.unbind(&ipc_addr) {
-            warn!("Failed to unbind IPC socket: {}", e);
-        } else {
-            info!("ZmqReceiver ipc_socket unbind success");
+        for endpoint in &self.endpoints {
+            if let Err(e) = self.ipc_socket.disconnect(endpoint) {
+                warn!("Failed to disconnect {}: {}", endpoint, e);
+            } else {
+                info!("ZmqReceiver disconnect success, endpoint: {}", endpoint);
+            }
         }
         
         info!("ZmqReceiver stopped");
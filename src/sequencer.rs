@@ -0,0 +1,274 @@
+// period 排序: 在小窗口内重排乱序到达的 period, 按序输出, 超时仍缺失的 period 输出为缺口
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use crate::config::SequencerConfig;
+
+// 记录缺失 period 的数量上限
+const MISSING_HISTORY: usize = 1024;
+
+/// 排序后的输出
+pub enum Sequenced<T> {
+    Period(i64, T),
+    // 等待超时仍未收到的 period, 需要在 Stream 中写入缺口标记
+    Gap(i64),
+    // 一次缺失超过 `max_gap_entries` 个 period 时的整段缺口, 首尾都包含在内
+    GapRange(i64, i64),
+}
+
+pub struct PeriodSequencer<T> {
+    name: String,
+    window: i64,
+    gap_timeout: Duration,
+    max_gap_entries: i64,
+    // 下一个应当输出的 period, 收到第一条消息前为 None
+    next: Option<i64>,
+    buffer: BTreeMap<i64, T>,
+    // 开始等待 `next` 的时间, 缓冲区为空时为 None
+    waiting_since: Option<Instant>,
+    missing: BTreeSet<i64>,
+    // 按范围标记跳过的 period 数, 这些 period 不记入 `missing`
+    range_skipped: u64,
+}
+
+impl<T> PeriodSequencer<T> {
+    pub fn new(name: &str, cfg: &SequencerConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            window: cfg.window.max(1) as i64,
+            gap_timeout: Duration::from_millis(cfg.gap_timeout_ms),
+            max_gap_entries: cfg.max_gap_entries as i64,
+            next: None,
+            buffer: BTreeMap::new(),
+            waiting_since: None,
+            missing: BTreeSet::new(),
+            range_skipped: 0,
+        }
+    }
+
+    /// 放入一个 period, 返回现在可以按序输出的结果
+    pub fn push(&mut self, period: i64, item: T) -> Vec<Sequenced<T>> {
+        let Some(next) = self.next else {
+            self.next = Some(period + 1);
+            return vec![Sequenced::Period(period, item)];
+        };
+
+        if period < next {
+            // 已输出过的 period 的新版本, 或已记为缺口后才到达的 period, 直接输出
+            if self.missing.remove(&period) {
+                info!("[{}] 缺失的 period {} 迟到到达", self.name, period);
+            }
+            return vec![Sequenced::Period(period, item)];
+        }
+
+        if period == next {
+            let mut out = vec![Sequenced::Period(period, item)];
+            self.next = Some(period + 1);
+            self.drain_ready(&mut out);
+            return out;
+        }
+
+        // 前面还有未到达的 period, 先缓存
+        if self.buffer.insert(period, item).is_some() {
+            info!("[{}] period {} 在重排窗口内重复到达, 使用新版本", self.name, period);
+        }
+        self.waiting_since.get_or_insert_with(Instant::now);
+
+        // 超出窗口时不再等待, 把缺失的 period 记为缺口
+        let mut out = Vec::new();
+        while let Some(next) = self.next {
+            let last = *self.buffer.last_key_value().map(|(p, _)| p).unwrap_or(&next);
+            if last - next < self.window {
+                break;
+            }
+            self.skip_to_buffered(&mut out);
+        }
+        out
+    }
+
+    /// 等待中的缺失 period 的超时时间
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.waiting_since.map(|t| t + self.gap_timeout)
+    }
+
+    /// 等待超时时, 把缺失的 period 记为缺口并输出其后已缓存的 period
+    pub fn on_timeout(&mut self, now: Instant) -> Vec<Sequenced<T>> {
+        let mut out = Vec::new();
+        if self.next_deadline().is_some_and(|deadline| deadline <= now) {
+            self.skip_to_buffered(&mut out);
+        }
+        out
+    }
+
+    /// 关闭时按序输出全部已缓存的 period, 不再记录缺口
+    pub fn take_all(&mut self) -> Vec<Sequenced<T>> {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.waiting_since = None;
+        buffer
+            .into_iter()
+            .map(|(period, item)| Sequenced::Period(period, item))
+            .collect()
+    }
+
+    /// 记录过的缺失 period, 迟到补齐的会被移除
+    pub fn missing(&self) -> &BTreeSet<i64> {
+        &self.missing
+    }
+
+    /// 按范围标记跳过的 period 总数
+    pub fn range_skipped(&self) -> u64 {
+        self.range_skipped
+    }
+
+    // 把 next 到第一个已缓存 period 之间的 period 记为缺口, 然后输出连续的部分;
+    // 缺失超过 max_gap_entries 个时只输出一个范围缺口
+    fn skip_to_buffered(&mut self, out: &mut Vec<Sequenced<T>>) {
+        let (Some(next), Some(&first)) = (self.next, self.buffer.keys().next()) else {
+            self.waiting_since = None;
+            return;
+        };
+        let count = first - next;
+        if count > self.max_gap_entries {
+            warn!(
+                "[{}] period {}..{} 缺失 {} 个, 超过 max_gap_entries, 记为一个范围缺口",
+                self.name,
+                next,
+                first - 1,
+                count
+            );
+            self.range_skipped += count as u64;
+            out.push(Sequenced::GapRange(next, first - 1));
+        } else {
+            warn!("[{}] period {}..{} 缺失, 记为缺口", self.name, next, first - 1);
+            for period in next..first {
+                self.missing.insert(period);
+                out.push(Sequenced::Gap(period));
+            }
+        }
+        while self.missing.len() > MISSING_HISTORY {
+            self.missing.pop_first();
+        }
+        self.next = Some(first);
+        self.drain_ready(out);
+    }
+
+    // 输出从 next 开始连续的已缓存 period
+    fn drain_ready(&mut self, out: &mut Vec<Sequenced<T>>) {
+        while let Some(next) = self.next {
+            let Some(item) = self.buffer.remove(&next) else {
+                break;
+            };
+            out.push(Sequenced::Period(next, item));
+            self.next = Some(next + 1);
+        }
+        // 仍有缓存说明还在等待新的缺失 period, 重新计时
+        self.waiting_since = if self.buffer.is_empty() { None } else { Some(Instant::now()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequencer(window: usize, max_gap_entries: usize) -> PeriodSequencer<()> {
+        let cfg = SequencerConfig {
+            enabled: true,
+            window,
+            gap_timeout_ms: 9_000,
+            max_gap_entries,
+        };
+        PeriodSequencer::new("test", &cfg)
+    }
+
+    // Period 输出为正数, Gap 输出为负数, GapRange 输出为首尾的负数
+    fn flatten(out: Vec<Sequenced<()>>) -> Vec<i64> {
+        out.into_iter()
+            .flat_map(|item| match item {
+                Sequenced::Period(period, ()) => vec![period],
+                Sequenced::Gap(period) => vec![-period],
+                Sequenced::GapRange(first, last) => vec![-first, -last],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn in_order_passes_through() {
+        let mut seq = sequencer(8, 16);
+        assert_eq!(flatten(seq.push(100, ())), vec![100]);
+        assert_eq!(flatten(seq.push(101, ())), vec![101]);
+        assert!(seq.next_deadline().is_none());
+    }
+
+    #[test]
+    fn reorders_within_window() {
+        let mut seq = sequencer(8, 16);
+        seq.push(100, ());
+        assert!(seq.push(102, ()).is_empty());
+        assert!(seq.push(103, ()).is_empty());
+        assert!(seq.next_deadline().is_some());
+        assert_eq!(flatten(seq.push(101, ())), vec![101, 102, 103]);
+        assert!(seq.next_deadline().is_none());
+        assert!(seq.missing().is_empty());
+    }
+
+    #[test]
+    fn timeout_emits_gap() {
+        let mut seq = sequencer(8, 16);
+        seq.push(100, ());
+        seq.push(102, ());
+        let deadline = seq.next_deadline().unwrap();
+        assert!(seq.on_timeout(deadline - Duration::from_millis(1)).is_empty());
+        assert_eq!(flatten(seq.on_timeout(deadline)), vec![-101, 102]);
+        assert!(seq.next_deadline().is_none());
+        assert!(seq.missing().contains(&101));
+    }
+
+    #[test]
+    fn beyond_window_skips_without_waiting() {
+        let mut seq = sequencer(8, 16);
+        seq.push(100, ());
+        assert_eq!(flatten(seq.push(110, ())), vec![-101, -102, -103, -104, -105, -106, -107, -108, -109, 110]);
+        assert_eq!(seq.missing().len(), 9);
+    }
+
+    #[test]
+    fn large_jump_emits_one_range() {
+        let mut seq = sequencer(8, 16);
+        seq.push(100, ());
+        assert_eq!(flatten(seq.push(100_000, ())), vec![-101, -99_999, 100_000]);
+        assert!(seq.missing().is_empty());
+        assert_eq!(seq.range_skipped(), 99_899);
+        assert_eq!(flatten(seq.push(100_001, ())), vec![100_001]);
+    }
+
+    #[test]
+    fn max_gap_entries_is_inclusive() {
+        let mut seq = sequencer(1, 3);
+        seq.push(100, ());
+        assert_eq!(flatten(seq.push(104, ())), vec![-101, -102, -103, 104]);
+        assert_eq!(flatten(seq.push(109, ())), vec![-105, -108, 109]);
+    }
+
+    #[test]
+    fn late_period_passes_through() {
+        let mut seq = sequencer(8, 16);
+        seq.push(100, ());
+        seq.push(102, ());
+        seq.on_timeout(seq.next_deadline().unwrap());
+        assert_eq!(flatten(seq.push(101, ())), vec![101]);
+        assert!(seq.missing().is_empty());
+        // 已输出过的 period 的新版本同样直接输出
+        assert_eq!(flatten(seq.push(102, ())), vec![102]);
+    }
+
+    #[test]
+    fn take_all_flushes_buffer_without_gaps() {
+        let mut seq = sequencer(8, 16);
+        seq.push(100, ());
+        seq.push(103, ());
+        seq.push(102, ());
+        assert_eq!(flatten(seq.take_all()), vec![102, 103]);
+        assert!(seq.next_deadline().is_none());
+    }
+}
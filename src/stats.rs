@@ -0,0 +1,466 @@
+// 每个 period 每个 symbol 的行情统计, 以及跨 period 汇总的 K 线
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::book::{OrderBook, OrderBooks};
+use crate::message::{PeriodMessage, Side, SymbolInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BarInterval {
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "1h")]
+    H1,
+}
+
+impl BarInterval {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BarInterval::M1 => "1m",
+            BarInterval::M5 => "5m",
+            BarInterval::H1 => "1h",
+        }
+    }
+
+    pub fn millis(&self) -> i64 {
+        match self {
+            BarInterval::M1 => 60_000,
+            BarInterval::M5 => 300_000,
+            BarInterval::H1 => 3_600_000,
+        }
+    }
+
+    // period 的 ts 为窗口结束时间, 用 ts - 1 归入所在的 K 线
+    fn bucket(&self, period_ts: i64) -> i64 {
+        (period_ts - 1).div_euclid(self.millis()) * self.millis()
+    }
+}
+
+/// 按 incs 重建的盘口在每条 inc 之后的买卖价差和中间价统计
+///
+/// 只统计已收到快照, 两侧都有报价且没有交叉的时刻。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpreadStats {
+    pub samples: u64,
+    pub min_spread: f64,
+    pub max_spread: f64,
+    pub mean_spread: f64,
+    pub mean_mid: f64,
+    pub last_mid: f64,
+}
+
+impl SpreadStats {
+    fn merge(&mut self, other: &SpreadStats) {
+        let total = (self.samples + other.samples) as f64;
+        self.mean_spread = (self.mean_spread * self.samples as f64 + other.mean_spread * other.samples as f64) / total;
+        self.mean_mid = (self.mean_mid * self.samples as f64 + other.mean_mid * other.samples as f64) / total;
+        self.samples += other.samples;
+        self.min_spread = self.min_spread.min(other.min_spread);
+        self.max_spread = self.max_spread.max(other.max_spread);
+        self.last_mid = other.last_mid;
+    }
+}
+
+/// 一个 symbol 在一个 period 或一根 K 线内的统计
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SymbolStats {
+    pub symbol: String,
+    pub trade_count: u64,
+    // 没有成交时 OHLC 和 VWAP 为 None
+    pub open: Option<f64>,
+    pub high: Option<f64>,
+    pub low: Option<f64>,
+    pub close: Option<f64>,
+    pub volume: f64,
+    pub notional: f64,
+    pub vwap: Option<f64>,
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+    pub spread: Option<SpreadStats>,
+}
+
+impl SymbolStats {
+    fn empty(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            trade_count: 0,
+            open: None,
+            high: None,
+            low: None,
+            close: None,
+            volume: 0.0,
+            notional: 0.0,
+            vwap: None,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            spread: None,
+        }
+    }
+
+    /// 计算一个 symbol 在该 period 内的统计, 同时把 incs 应用到 `book`
+    ///
+    /// `trade_count` 只统计价格和数量都为正数的成交。
+    /// `book` 应为该 period 开始时的盘口, 价差和中间价依赖它。
+    pub fn compute(info: &SymbolInfo, book: &mut OrderBook) -> Self {
+        let mut stats = Self::empty(&info.symbol);
+        // 价格或数量无效的成交不计入, 见 `PeriodMessage::validate`
+        for trade in info.trades.iter().filter(|t| is_valid(t.price) && is_valid(t.amount)) {
+            stats.trade_count += 1;
+            stats.open.get_or_insert(trade.price);
+            stats.high = Some(stats.high.map_or(trade.price, |h| h.max(trade.price)));
+            stats.low = Some(stats.low.map_or(trade.price, |l| l.min(trade.price)));
+            stats.close = Some(trade.price);
+            stats.volume += trade.amount;
+            stats.notional += trade.price * trade.amount;
+            match trade.side {
+                Side::Buy => stats.buy_volume += trade.amount,
+                Side::Sell => stats.sell_volume += trade.amount,
+                Side::Unknown(_) => {}
+            }
+        }
+        stats.vwap = (stats.volume > 0.0).then(|| stats.notional / stats.volume);
+
+        for inc in &info.incs {
+            book.apply(inc);
+            // 快照之前只有增量修改过的档位, 买一卖一没有意义
+            if !book.has_snapshot() {
+                continue;
+            }
+            let (Some(spread), Some(mid)) = (book.spread(), book.mid()) else {
+                continue;
+            };
+            if spread <= 0.0 {
+                continue;
+            }
+            let sample = SpreadStats {
+                samples: 1,
+                min_spread: spread,
+                max_spread: spread,
+                mean_spread: spread,
+                mean_mid: mid,
+                last_mid: mid,
+            };
+            match stats.spread.as_mut() {
+                Some(s) => s.merge(&sample),
+                None => stats.spread = Some(sample),
+            }
+        }
+        stats
+    }
+
+    fn merge(&mut self, other: &SymbolStats) {
+        self.trade_count += other.trade_count;
+        if self.open.is_none() {
+            self.open = other.open;
+        }
+        self.high = max_opt(self.high, other.high);
+        self.low = min_opt(self.low, other.low);
+        if other.close.is_some() {
+            self.close = other.close;
+        }
+        self.volume += other.volume;
+        self.notional += other.notional;
+        self.vwap = (self.volume > 0.0).then(|| self.notional / self.volume);
+        self.buy_volume += other.buy_volume;
+        self.sell_volume += other.sell_volume;
+        match (self.spread.as_mut(), &other.spread) {
+            (Some(s), Some(o)) => s.merge(o),
+            (None, Some(o)) => self.spread = Some(o.clone()),
+            _ => {}
+        }
+    }
+}
+
+fn is_valid(value: f64) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+fn max_opt(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn min_opt(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeriodStats {
+    pub period: i64,
+    pub ts: i64,
+    pub symbols: Vec<SymbolStats>,
+}
+
+/// 一根 K 线, 起止时间为 `[start_ts, start_ts + interval)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    pub interval: BarInterval,
+    pub start_ts: i64,
+    // 参与汇总的 period 数
+    pub periods: u64,
+    #[serde(flatten)]
+    pub stats: SymbolStats,
+}
+
+/// 把连续 period 的统计汇总为固定周期的 K 线
+///
+/// period 需按顺序送入; 进入下一根 K 线时输出上一根的全部 symbol。
+pub struct BarAggregator {
+    interval: BarInterval,
+    start_ts: Option<i64>,
+    bars: BTreeMap<String, Bar>,
+}
+
+impl BarAggregator {
+    pub fn new(interval: BarInterval) -> Self {
+        Self {
+            interval,
+            start_ts: None,
+            bars: BTreeMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> BarInterval {
+        self.interval
+    }
+
+    /// 放入一个 period 的统计, 返回已经结束的 K 线
+    pub fn push(&mut self, stats: &PeriodStats) -> Vec<Bar> {
+        let bucket = self.interval.bucket(stats.ts);
+        let mut done = Vec::new();
+        match self.start_ts {
+            Some(start) if bucket < start => {
+                warn!(
+                    "period {} 早于当前 {} K 线 {}, 不计入",
+                    stats.period,
+                    self.interval.as_str(),
+                    start
+                );
+                return done;
+            }
+            Some(start) if bucket > start => done = self.flush(),
+            _ => {}
+        }
+        self.start_ts = Some(bucket);
+        for symbol in &stats.symbols {
+            match self.bars.get_mut(&symbol.symbol) {
+                Some(bar) => {
+                    bar.periods += 1;
+                    bar.stats.merge(symbol);
+                }
+                None => {
+                    self.bars.insert(
+                        symbol.symbol.clone(),
+                        Bar {
+                            interval: self.interval,
+                            start_ts: bucket,
+                            periods: 1,
+                            stats: symbol.clone(),
+                        },
+                    );
+                }
+            }
+        }
+        done
+    }
+
+    /// 取出当前尚未结束的 K 线
+    pub fn flush(&mut self) -> Vec<Bar> {
+        std::mem::take(&mut self.bars).into_values().collect()
+    }
+}
+
+/// 一个行情源的统计: 维护各 symbol 的盘口, 计算 period 统计并汇总 K 线
+pub struct StatsEngine {
+    books: OrderBooks,
+    aggregators: Vec<BarAggregator>,
+}
+
+impl StatsEngine {
+    pub fn new(intervals: &[BarInterval]) -> Self {
+        Self {
+            books: OrderBooks::new(),
+            aggregators: intervals.iter().map(|i| BarAggregator::new(*i)).collect(),
+        }
+    }
+
+    /// 处理一个 period, 返回它的统计和因此结束的 K 线
+    ///
+    /// 已处理过的 period 的新版本和迟到的 period 不再计入, 返回 None。
+    pub fn process(&mut self, msg: &PeriodMessage) -> Option<(PeriodStats, Vec<Bar>)> {
+        if self.books.last_period().is_some_and(|last| msg.period <= last) {
+            return None;
+        }
+        if !self.books.advance(msg.period) {
+            warn!("period {} 与上一个 period 不连续, 下一个快照前盘口可能不准确", msg.period);
+        }
+        let stats = PeriodStats {
+            period: msg.period,
+            ts: msg.ts,
+            symbols: msg
+                .symbol_infos
+                .iter()
+                .map(|info| SymbolStats::compute(info, self.books.book_mut(&info.symbol)))
+                .collect(),
+        };
+        let bars = self.aggregators.iter_mut().flat_map(|a| a.push(&stats)).collect();
+        Some((stats, bars))
+    }
+
+    /// 取出各周期尚未结束的 K 线, 退出前调用
+    pub fn flush(&mut self) -> Vec<Bar> {
+        self.aggregators.iter_mut().flat_map(|a| a.flush()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{IncrementOrderBookInfo, PriceLevel, TradeInfo};
+
+    fn trade(side: &str, price: f64, amount: f64) -> TradeInfo {
+        TradeInfo {
+            timestamp: 0,
+            side: Side::parse(side),
+            price,
+            amount,
+        }
+    }
+
+    fn inc(is_snapshot: bool, bid: (f64, f64), ask: (f64, f64)) -> IncrementOrderBookInfo {
+        IncrementOrderBookInfo {
+            timestamp: 0,
+            is_snapshot,
+            bids: vec![PriceLevel { price: bid.0, amount: bid.1 }],
+            asks: vec![PriceLevel { price: ask.0, amount: ask.1 }],
+        }
+    }
+
+    fn symbol(trades: Vec<TradeInfo>, incs: Vec<IncrementOrderBookInfo>) -> SymbolInfo {
+        SymbolInfo {
+            symbol: "BTC".to_string(),
+            trades,
+            incs,
+        }
+    }
+
+    fn period(period: i64, ts: i64, trades: Vec<TradeInfo>) -> PeriodMessage {
+        PeriodMessage {
+            period,
+            ts,
+            post_ts: ts,
+            poster_id: "p".to_string(),
+            symbol_infos: vec![symbol(trades, vec![])],
+        }
+    }
+
+    #[test]
+    fn ohlc_and_vwap() {
+        let info = symbol(
+            vec![
+                trade("B", 10.0, 1.0),
+                trade("S", 12.0, 2.0),
+                trade("B", 9.0, 1.0),
+                trade("S", 11.0, 4.0),
+            ],
+            vec![],
+        );
+        let stats = SymbolStats::compute(&info, &mut OrderBook::new());
+        assert_eq!(stats.trade_count, 4);
+        assert_eq!(stats.open, Some(10.0));
+        assert_eq!(stats.high, Some(12.0));
+        assert_eq!(stats.low, Some(9.0));
+        assert_eq!(stats.close, Some(11.0));
+        assert_eq!(stats.volume, 8.0);
+        assert_eq!(stats.notional, 10.0 + 24.0 + 9.0 + 44.0);
+        assert_eq!(stats.vwap, Some(87.0 / 8.0));
+    }
+
+    #[test]
+    fn invalid_trades_are_skipped() {
+        let info = symbol(
+            vec![trade("B", 0.0, 1.0), trade("B", 10.0, -1.0), trade("B", f64::NAN, 1.0)],
+            vec![],
+        );
+        let stats = SymbolStats::compute(&info, &mut OrderBook::new());
+        assert_eq!(stats.trade_count, 0);
+        assert_eq!(stats.open, None);
+        assert_eq!(stats.vwap, None);
+    }
+
+    #[test]
+    fn volume_is_split_by_side() {
+        let info = symbol(
+            vec![trade("buy", 10.0, 1.5), trade("S", 10.0, 2.0), trade("B", 10.0, 0.5), trade("x", 10.0, 3.0)],
+            vec![],
+        );
+        let stats = SymbolStats::compute(&info, &mut OrderBook::new());
+        assert_eq!(stats.buy_volume, 2.0);
+        assert_eq!(stats.sell_volume, 2.0);
+        // 方向未知的成交只计入总量
+        assert_eq!(stats.volume, 7.0);
+    }
+
+    #[test]
+    fn spread_is_sampled_only_after_a_snapshot() {
+        let mut book = OrderBook::new();
+        let before = symbol(vec![], vec![inc(false, (99.0, 1.0), (101.0, 1.0))]);
+        assert_eq!(SymbolStats::compute(&before, &mut book).spread, None);
+
+        let after = symbol(
+            vec![],
+            vec![inc(true, (100.0, 1.0), (101.0, 1.0)), inc(false, (100.0, 1.0), (104.0, 1.0))],
+        );
+        // 第二条增量后卖一仍为 101
+        let spread = SymbolStats::compute(&after, &mut book).spread.unwrap();
+        assert_eq!(spread.samples, 2);
+        assert_eq!(spread.min_spread, 1.0);
+        assert_eq!(spread.max_spread, 1.0);
+        assert_eq!(spread.last_mid, 100.5);
+    }
+
+    #[test]
+    fn bar_rolls_over_at_the_interval_boundary() {
+        let mut engine = StatsEngine::new(&[BarInterval::M1]);
+        // ts 为窗口结束时间, 60_000 仍属于第一根 K 线
+        let (_, bars) = engine.process(&period(1, 30_000, vec![trade("B", 10.0, 1.0)])).unwrap();
+        assert!(bars.is_empty());
+        let (_, bars) = engine.process(&period(2, 60_000, vec![trade("S", 12.0, 1.0)])).unwrap();
+        assert!(bars.is_empty());
+
+        let (_, bars) = engine.process(&period(3, 90_000, vec![trade("B", 8.0, 2.0)])).unwrap();
+        assert_eq!(bars.len(), 1);
+        let bar = &bars[0];
+        assert_eq!(bar.start_ts, 0);
+        assert_eq!(bar.periods, 2);
+        assert_eq!(bar.stats.open, Some(10.0));
+        assert_eq!(bar.stats.high, Some(12.0));
+        assert_eq!(bar.stats.close, Some(12.0));
+        assert_eq!(bar.stats.vwap, Some(11.0));
+        assert_eq!(bar.stats.buy_volume, 1.0);
+        assert_eq!(bar.stats.sell_volume, 1.0);
+
+        // 退出时取出尚未结束的 K 线
+        let open = engine.flush();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].start_ts, 60_000);
+        assert_eq!(open[0].stats.close, Some(8.0));
+        assert!(engine.flush().is_empty());
+    }
+
+    #[test]
+    fn stale_periods_are_ignored() {
+        let mut engine = StatsEngine::new(&[BarInterval::M1]);
+        assert!(engine.process(&period(5, 30_000, vec![])).is_some());
+        assert!(engine.process(&period(5, 30_000, vec![])).is_none());
+        assert!(engine.process(&period(4, 20_000, vec![])).is_none());
+    }
+}
@@ -0,0 +1,436 @@
+// 解码后 period 的数据质量检查
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::message::{IncrementOrderBookInfo, PeriodMessage, PriceLevel};
+
+// 上游处理器产出的 period 长度, period 的 ts 为窗口结束时间
+pub const DEFAULT_PERIOD_MS: i64 = 3_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    // 不影响使用, 但说明上游行为异常
+    Warning,
+    // 下游按该数据计算会得到错误结果
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    UnknownSide,
+    InvalidPrice,
+    InvalidAmount,
+    // 快照中买一高于卖一
+    CrossedBook,
+    // 快照中买一等于卖一
+    LockedBook,
+    UnsortedBids,
+    UnsortedAsks,
+    TimestampOutOfWindow,
+    NonMonotonicTimestamp,
+    DuplicateSymbol,
+}
+
+impl ViolationKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ViolationKind::UnknownSide
+            | ViolationKind::InvalidPrice
+            | ViolationKind::InvalidAmount
+            | ViolationKind::CrossedBook
+            | ViolationKind::DuplicateSymbol => Severity::Error,
+            ViolationKind::LockedBook
+            | ViolationKind::UnsortedBids
+            | ViolationKind::UnsortedAsks
+            | ViolationKind::TimestampOutOfWindow
+            | ViolationKind::NonMonotonicTimestamp => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    pub symbol: String,
+    pub kind: ViolationKind,
+    pub severity: Severity,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub period: i64,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// 最严重的一条违规, 没有违规时为 None
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.violations.iter().map(|v| v.severity).max()
+    }
+
+    /// 是否有违规达到 `threshold`
+    pub fn fails(&self, threshold: Severity) -> bool {
+        self.max_severity().is_some_and(|s| s >= threshold)
+    }
+
+    /// 按 symbol 统计违规条数
+    pub fn count_by_symbol(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for v in &self.violations {
+            *counts.entry(v.symbol.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    fn push(&mut self, symbol: &str, kind: ViolationKind, detail: String) {
+        self.violations.push(Violation {
+            symbol: symbol.to_string(),
+            kind,
+            severity: kind.severity(),
+            detail,
+        });
+    }
+}
+
+/// 发布管道中按 symbol 累计的违规条数
+#[derive(Debug, Default)]
+pub struct ViolationCounter {
+    by_symbol: BTreeMap<String, u64>,
+    rejected_periods: u64,
+}
+
+impl ViolationCounter {
+    pub fn record(&mut self, report: &ValidationReport) {
+        for (symbol, count) in report.count_by_symbol() {
+            *self.by_symbol.entry(symbol.to_string()).or_insert(0) += count as u64;
+        }
+    }
+
+    pub fn record_rejected(&mut self) {
+        self.rejected_periods += 1;
+    }
+
+    pub fn by_symbol(&self) -> &BTreeMap<String, u64> {
+        &self.by_symbol
+    }
+
+    pub fn rejected_periods(&self) -> u64 {
+        self.rejected_periods
+    }
+}
+
+impl PeriodMessage {
+    /// 按默认的 period 长度检查数据质量
+    pub fn validate(&self) -> ValidationReport {
+        self.validate_with_period_ms(DEFAULT_PERIOD_MS)
+    }
+
+    /// 检查数据质量, 时间戳应落在 `[ts - period_ms, ts)` 内
+    ///
+    /// 价格和数量必须为正数; 增量中数量为 0 表示删除该档位, 不算违规。
+    /// 买卖盘排序和盘口交叉只检查快照, 增量中的档位没有顺序约定。
+    pub fn validate_with_period_ms(&self, period_ms: i64) -> ValidationReport {
+        let mut report = ValidationReport {
+            period: self.period,
+            violations: Vec::new(),
+        };
+        let window = (self.ts - period_ms)..self.ts;
+        let mut seen = HashSet::new();
+
+        for info in &self.symbol_infos {
+            let symbol = info.symbol.as_str();
+            if !seen.insert(symbol) {
+                report.push(symbol, ViolationKind::DuplicateSymbol, "symbol 重复出现".to_string());
+            }
+
+            let mut last_ts = i64::MIN;
+            for (i, trade) in info.trades.iter().enumerate() {
+                if !trade.side.is_known() {
+                    report.push(symbol, ViolationKind::UnknownSide, format!("trade {} side: {:?}", i, trade.side.as_str()));
+                }
+                if !is_positive(trade.price) {
+                    report.push(symbol, ViolationKind::InvalidPrice, format!("trade {} price: {}", i, trade.price));
+                }
+                if !is_positive(trade.amount) {
+                    report.push(symbol, ViolationKind::InvalidAmount, format!("trade {} amount: {}", i, trade.amount));
+                }
+                check_timestamp(&mut report, symbol, "trade", i, trade.timestamp, &window, &mut last_ts);
+            }
+
+            let mut last_ts = i64::MIN;
+            for (i, inc) in info.incs.iter().enumerate() {
+                check_levels(&mut report, symbol, i, inc);
+                check_timestamp(&mut report, symbol, "inc", i, inc.timestamp, &window, &mut last_ts);
+            }
+        }
+        report
+    }
+}
+
+fn is_positive(value: f64) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+fn check_timestamp(
+    report: &mut ValidationReport,
+    symbol: &str,
+    what: &str,
+    index: usize,
+    ts: i64,
+    window: &std::ops::Range<i64>,
+    last_ts: &mut i64,
+) {
+    if !window.contains(&ts) {
+        report.push(
+            symbol,
+            ViolationKind::TimestampOutOfWindow,
+            format!("{} {} timestamp {} 不在 [{}, {}) 内", what, index, ts, window.start, window.end),
+        );
+    }
+    if ts < *last_ts {
+        report.push(
+            symbol,
+            ViolationKind::NonMonotonicTimestamp,
+            format!("{} {} timestamp {} 早于前一条的 {}", what, index, ts, last_ts),
+        );
+    }
+    *last_ts = (*last_ts).max(ts);
+}
+
+fn check_levels(report: &mut ValidationReport, symbol: &str, index: usize, inc: &IncrementOrderBookInfo) {
+    for (side, levels) in [("bid", &inc.bids), ("ask", &inc.asks)] {
+        for level in levels {
+            if !is_positive(level.price) {
+                report.push(
+                    symbol,
+                    ViolationKind::InvalidPrice,
+                    format!("inc {} {} price: {}", index, side, level.price),
+                );
+            }
+            // 快照中的档位必须有数量, 增量中数量为 0 表示删除
+            let valid_amount = if inc.is_snapshot {
+                is_positive(level.amount)
+            } else {
+                level.amount.is_finite() && level.amount >= 0.0
+            };
+            if !valid_amount {
+                report.push(
+                    symbol,
+                    ViolationKind::InvalidAmount,
+                    format!("inc {} {} amount: {}", index, side, level.amount),
+                );
+            }
+        }
+    }
+
+    if !inc.is_snapshot {
+        return;
+    }
+    if !is_sorted_by(&inc.bids, |a, b| a.price > b.price) {
+        report.push(symbol, ViolationKind::UnsortedBids, format!("inc {} 买盘不是按价格降序", index));
+    }
+    if !is_sorted_by(&inc.asks, |a, b| a.price < b.price) {
+        report.push(symbol, ViolationKind::UnsortedAsks, format!("inc {} 卖盘不是按价格升序", index));
+    }
+    let best_bid = inc.bids.iter().map(|l| l.price).fold(f64::NAN, f64::max);
+    let best_ask = inc.asks.iter().map(|l| l.price).fold(f64::NAN, f64::min);
+    if best_bid > best_ask {
+        report.push(
+            symbol,
+            ViolationKind::CrossedBook,
+            format!("inc {} 买一 {} 高于卖一 {}", index, best_bid, best_ask),
+        );
+    } else if best_bid == best_ask {
+        report.push(
+            symbol,
+            ViolationKind::LockedBook,
+            format!("inc {} 买一卖一同为 {}", index, best_bid),
+        );
+    }
+}
+
+fn is_sorted_by(levels: &[PriceLevel], in_order: impl Fn(&PriceLevel, &PriceLevel) -> bool) -> bool {
+    levels.windows(2).all(|w| in_order(&w[0], &w[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Side, SymbolInfo, TradeInfo};
+
+    const TS: i64 = 30_000;
+
+    fn levels(levels: &[(f64, f64)]) -> Vec<PriceLevel> {
+        levels.iter().map(|&(price, amount)| PriceLevel { price, amount }).collect()
+    }
+
+    fn snapshot(timestamp: i64, bids: &[(f64, f64)], asks: &[(f64, f64)]) -> IncrementOrderBookInfo {
+        IncrementOrderBookInfo {
+            timestamp,
+            is_snapshot: true,
+            bids: levels(bids),
+            asks: levels(asks),
+        }
+    }
+
+    fn trade(timestamp: i64) -> TradeInfo {
+        TradeInfo {
+            timestamp,
+            side: Side::Buy,
+            price: 100.0,
+            amount: 1.0,
+        }
+    }
+
+    // 时间戳在窗口内, 快照排序正确且没有交叉
+    fn clean_symbol(symbol: &str) -> SymbolInfo {
+        SymbolInfo {
+            symbol: symbol.to_string(),
+            trades: vec![trade(TS - 2_000), trade(TS - 1_000)],
+            incs: vec![snapshot(TS - 2_000, &[(100.0, 1.0), (99.0, 1.0)], &[(101.0, 1.0), (102.0, 1.0)])],
+        }
+    }
+
+    fn message(symbol_infos: Vec<SymbolInfo>) -> PeriodMessage {
+        PeriodMessage {
+            period: 10,
+            ts: TS,
+            post_ts: TS,
+            poster_id: "p".to_string(),
+            symbol_infos,
+        }
+    }
+
+    fn kinds(report: &ValidationReport) -> Vec<ViolationKind> {
+        report.violations.iter().map(|v| v.kind).collect()
+    }
+
+    #[test]
+    fn clean_period_passes() {
+        let report = message(vec![clean_symbol("BTC"), clean_symbol("ETH")]).validate();
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(report.period, 10);
+        assert_eq!(report.max_severity(), None);
+    }
+
+    #[test]
+    fn crossed_and_locked_snapshots() {
+        let mut info = clean_symbol("BTC");
+        info.incs = vec![snapshot(TS - 1_000, &[(101.5, 1.0)], &[(101.0, 1.0)])];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::CrossedBook]);
+
+        info.incs = vec![snapshot(TS - 1_000, &[(101.0, 1.0)], &[(101.0, 1.0)])];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::LockedBook]);
+
+        // 增量不检查交叉
+        info.incs[0].is_snapshot = false;
+        assert!(message(vec![info]).validate().is_clean());
+    }
+
+    #[test]
+    fn snapshot_levels_must_be_sorted() {
+        let mut info = clean_symbol("BTC");
+        info.incs = vec![snapshot(TS - 1_000, &[(99.0, 1.0), (100.0, 1.0)], &[(101.0, 1.0), (102.0, 1.0)])];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::UnsortedBids]);
+
+        info.incs = vec![snapshot(TS - 1_000, &[(100.0, 1.0), (99.0, 1.0)], &[(102.0, 1.0), (101.0, 1.0)])];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::UnsortedAsks]);
+
+        info.incs[0].is_snapshot = false;
+        assert!(message(vec![info]).validate().is_clean());
+    }
+
+    #[test]
+    fn timestamps_must_fall_in_the_period_window() {
+        // 窗口为 [ts - period_ms, ts), 两端分别为闭区间和开区间
+        let mut info = clean_symbol("BTC");
+        info.trades = vec![trade(TS - DEFAULT_PERIOD_MS), trade(TS - 1)];
+        assert!(message(vec![info.clone()]).validate().is_clean());
+
+        info.trades = vec![trade(TS - DEFAULT_PERIOD_MS - 1)];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::TimestampOutOfWindow]);
+
+        info.trades = vec![trade(TS)];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::TimestampOutOfWindow]);
+
+        // 窗口随 period_ms 变化
+        info.trades = vec![trade(TS - 5_000)];
+        assert_eq!(kinds(&message(vec![info.clone()]).validate()), [ViolationKind::TimestampOutOfWindow]);
+        assert!(message(vec![info]).validate_with_period_ms(10_000).is_clean());
+    }
+
+    #[test]
+    fn timestamps_must_not_go_backwards() {
+        let mut info = clean_symbol("BTC");
+        info.trades = vec![trade(TS - 1_000), trade(TS - 1_000), trade(TS - 2_000)];
+        let report = message(vec![info.clone()]).validate();
+        assert_eq!(kinds(&report), [ViolationKind::NonMonotonicTimestamp]);
+        assert!(report.violations[0].detail.starts_with("trade 2"));
+
+        info.trades = vec![trade(TS - 1_000)];
+        info.incs = vec![
+            snapshot(TS - 1_000, &[(100.0, 1.0)], &[(101.0, 1.0)]),
+            snapshot(TS - 1_500, &[(100.0, 1.0)], &[(101.0, 1.0)]),
+        ];
+        assert_eq!(kinds(&message(vec![info]).validate()), [ViolationKind::NonMonotonicTimestamp]);
+    }
+
+    #[test]
+    fn duplicate_symbols_are_reported() {
+        let report = message(vec![clean_symbol("BTC"), clean_symbol("ETH"), clean_symbol("BTC")]).validate();
+        assert_eq!(kinds(&report), [ViolationKind::DuplicateSymbol]);
+        assert_eq!(report.violations[0].symbol, "BTC");
+    }
+
+    #[test]
+    fn delete_levels_are_only_allowed_in_deltas() {
+        let mut info = clean_symbol("BTC");
+        info.incs.push(IncrementOrderBookInfo {
+            timestamp: TS - 1_000,
+            is_snapshot: false,
+            bids: levels(&[(100.0, 0.0)]),
+            asks: vec![],
+        });
+        assert!(message(vec![info.clone()]).validate().is_clean());
+
+        info.incs[1].is_snapshot = true;
+        let report = message(vec![info]).validate();
+        assert!(kinds(&report).contains(&ViolationKind::InvalidAmount));
+    }
+
+    #[test]
+    fn reject_threshold_follows_the_max_severity() {
+        let mut info = clean_symbol("BTC");
+        info.incs = vec![snapshot(TS - 1_000, &[(101.0, 1.0)], &[(101.0, 1.0)])];
+        let locked = message(vec![info.clone()]).validate();
+        assert_eq!(locked.max_severity(), Some(Severity::Warning));
+        assert!(locked.fails(Severity::Warning));
+        assert!(!locked.fails(Severity::Error));
+
+        info.trades[0].side = Side::parse("bid");
+        let unknown_side = message(vec![info]).validate();
+        assert_eq!(unknown_side.max_severity(), Some(Severity::Error));
+        assert!(unknown_side.fails(Severity::Warning));
+        assert!(unknown_side.fails(Severity::Error));
+
+        let clean = message(vec![clean_symbol("BTC")]).validate();
+        assert!(!clean.fails(Severity::Warning));
+    }
+
+    #[test]
+    fn counter_accumulates_by_symbol() {
+        let mut counter = ViolationCounter::default();
+        let report = message(vec![clean_symbol("BTC"), clean_symbol("BTC")]).validate();
+        counter.record(&report);
+        counter.record(&report);
+        counter.record_rejected();
+        assert_eq!(counter.by_symbol().get("BTC"), Some(&2));
+        assert_eq!(counter.rejected_periods(), 1);
+    }
+}
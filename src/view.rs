@@ -0,0 +1,351 @@
+// 直接在 capnp 数据上读取 period, 不构造 PeriodMessage
+//
+// 未压缩的数据直接借用, 压缩的数据只分配一次解压缓冲区;
+// 字段和列表都从 capnp Reader 按需读取, 不为每条 trade / inc 分配内存。
+use anyhow::Result;
+use capnp::message::ReaderOptions;
+use capnp::serialize::BufferSegments;
+use log::{info, warn};
+use std::borrow::Cow;
+
+use crate::compression::decompress_auto;
+use crate::config::DecodeConfig;
+use crate::message::{LevelAction, PriceLevel, Side};
+use crate::period_capnp::{increment_order_book_info, period_message, price_level, symbol_info, trade_info};
+
+/// capnp 解码错误中需要调用方区别处理的类别, 通过 `anyhow::Error::downcast_ref` 取得
+#[derive(Debug)]
+pub enum DecodeError {
+    /// 超过 `ReaderOptions` 的遍历或嵌套限制
+    TooLarge(capnp::Error),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::TooLarge(e) => write!(f, "period 超过解码限制: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::TooLarge(e) => Some(e),
+        }
+    }
+}
+
+impl DecodeError {
+    /// 超过解码限制的 capnp 错误转为 `DecodeError::TooLarge`, 其他错误原样返回
+    pub fn classify(err: anyhow::Error) -> anyhow::Error {
+        let too_large = err.downcast_ref::<capnp::Error>().is_some_and(|e| {
+            matches!(
+                e.kind,
+                capnp::ErrorKind::ReadLimitExceeded
+                    | capnp::ErrorKind::MessageTooLarge(_)
+                    | capnp::ErrorKind::NestingLimitExceeded
+            )
+        });
+        if !too_large {
+            return err;
+        }
+        match err.downcast::<capnp::Error>() {
+            Ok(e) => DecodeError::TooLarge(e).into(),
+            Err(err) => err,
+        }
+    }
+
+    pub fn is_too_large(err: &anyhow::Error) -> bool {
+        matches!(err.downcast_ref::<DecodeError>(), Some(DecodeError::TooLarge(_)))
+    }
+}
+
+/// 按 `cfg` 的限制读取头部和条数, `print_info` 为 true 时同时打印各 symbol 条数
+///
+/// `is_compressed` 与 [`PeriodView::new`] 相同。超过限制且开启 `oversize_fallback`
+/// 时改用 [`PeriodView::count_unlimited`] 统计条数。
+pub fn summarize(data: &[u8], is_compressed: bool, cfg: &DecodeConfig, print_info: bool) -> Result<PeriodSummary> {
+    let result = PeriodView::with_options(data, is_compressed, cfg.reader_options()).and_then(|view| {
+        if print_info {
+            view.print_info()?;
+        }
+        view.summary()
+    });
+    match result {
+        Err(e) if cfg.oversize_fallback && DecodeError::is_too_large(&e) => {
+            let summary = PeriodView::count_unlimited(data, is_compressed)?;
+            warn!(
+                "period {} {}, 按条数统计结果原样发布, info_count: {}",
+                summary.period,
+                e,
+                summary.info_count()
+            );
+            Ok(summary)
+        }
+        result => result,
+    }
+}
+
+/// 发布路径需要的 period 头部信息和条数
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodSummary {
+    pub period: i64,
+    pub ts: i64,
+    pub post_ts: i64,
+    pub poster_id: String,
+    pub symbol_count: usize,
+    pub trade_count: u64,
+    pub inc_count: u64,
+}
+
+impl PeriodSummary {
+    /// 与 `PeriodMessage::total_info_count` 相同: inc 数加 trade 数
+    pub fn info_count(&self) -> u64 {
+        self.inc_count + self.trade_count
+    }
+}
+
+/// 读取根结构; 根指针为空时报错, 避免把随意的数据当作全为默认值的 period
+pub(crate) fn period_root<S: capnp::message::ReaderSegments>(
+    message: &capnp::message::Reader<S>,
+) -> Result<period_message::Reader<'_>> {
+    let root = message.get_root::<capnp::any_pointer::Reader>()?;
+    if root.is_null() {
+        anyhow::bail!("capnp 消息的根指针为空");
+    }
+    Ok(root.get_as::<period_message::Reader>()?)
+}
+
+pub struct PeriodView<'a> {
+    message: capnp::message::Reader<BufferSegments<Cow<'a, [u8]>>>,
+}
+
+impl<'a> PeriodView<'a> {
+    /// `is_compressed` 为 true 时按魔数识别压缩格式, 见 [`Compression::detect`](crate::Compression::detect)
+    pub fn new(data: &'a [u8], is_compressed: bool) -> Result<Self> {
+        Self::with_options(data, is_compressed, ReaderOptions::new())
+    }
+
+    /// 使用指定的遍历和嵌套限制, 超过限制时读取字段返回 [`DecodeError::TooLarge`]
+    pub fn with_options(data: &'a [u8], is_compressed: bool, options: ReaderOptions) -> Result<Self> {
+        let data = if is_compressed {
+            decompress_auto(data)?
+        } else {
+            Cow::Borrowed(data)
+        };
+        let segments = BufferSegments::new(data, options).map_err(|e| DecodeError::classify(e.into()))?;
+        Ok(Self {
+            message: capnp::message::Reader::new(segments, options),
+        })
+    }
+
+    /// 不限制遍历量的条数统计, 用于超过解码限制的 period
+    ///
+    /// 只读取各列表的长度, 不访问 trade 和 inc 的内容。
+    pub fn count_unlimited(data: &'a [u8], is_compressed: bool) -> Result<PeriodSummary> {
+        let mut options = ReaderOptions::new();
+        options.traversal_limit_in_words(None);
+        Self::with_options(data, is_compressed, options)?.summary()
+    }
+
+    pub fn root(&self) -> Result<period_message::Reader<'_>> {
+        period_root(&self.message)
+    }
+
+    pub fn period(&self) -> Result<i64> {
+        Ok(self.root()?.get_period())
+    }
+
+    pub fn ts(&self) -> Result<i64> {
+        Ok(self.root()?.get_ts())
+    }
+
+    pub fn post_ts(&self) -> Result<i64> {
+        Ok(self.root()?.get_post_ts())
+    }
+
+    pub fn poster_id(&self) -> Result<&str> {
+        Ok(self.root()?.get_poster_id()?.to_str()?)
+    }
+
+    pub fn symbol_count(&self) -> Result<usize> {
+        Ok(self.root()?.get_symbol_infos()?.len() as usize)
+    }
+
+    pub fn symbols(&self) -> Result<impl Iterator<Item = SymbolView<'_>>> {
+        Ok(self.root()?.get_symbol_infos()?.iter().map(|reader| SymbolView { reader }))
+    }
+
+    pub fn trade_count(&self) -> Result<u64> {
+        let mut count = 0;
+        for symbol in self.symbols()? {
+            count += symbol.trade_count()? as u64;
+        }
+        Ok(count)
+    }
+
+    pub fn inc_count(&self) -> Result<u64> {
+        let mut count = 0;
+        for symbol in self.symbols()? {
+            count += symbol.inc_count()? as u64;
+        }
+        Ok(count)
+    }
+
+    /// inc 数加 trade 数, 即发布到 Stream 的 info_count
+    pub fn total_info_count(&self) -> Result<u64> {
+        Ok(self.inc_count()? + self.trade_count()?)
+    }
+
+    pub fn summary(&self) -> Result<PeriodSummary> {
+        self.read_summary().map_err(DecodeError::classify)
+    }
+
+    fn read_summary(&self) -> Result<PeriodSummary> {
+        let root = self.root()?;
+        let mut trade_count = 0;
+        let mut inc_count = 0;
+        for symbol in self.symbols()? {
+            trade_count += symbol.trade_count()? as u64;
+            inc_count += symbol.inc_count()? as u64;
+        }
+        Ok(PeriodSummary {
+            period: root.get_period(),
+            ts: root.get_ts(),
+            post_ts: root.get_post_ts(),
+            poster_id: root.get_poster_id()?.to_str()?.to_string(),
+            symbol_count: root.get_symbol_infos()?.len() as usize,
+            trade_count,
+            inc_count,
+        })
+    }
+
+    /// 输出内容与 `PeriodMessage::print_info` 相同
+    pub fn print_info(&self) -> Result<()> {
+        self.write_info().map_err(DecodeError::classify)
+    }
+
+    fn write_info(&self) -> Result<()> {
+        let root = self.root()?;
+        info!("Period Message Info:");
+        info!("  Period: {}", root.get_period());
+        info!("  Timestamp: {}", root.get_ts());
+        info!("  Post Timestamp: {}", root.get_post_ts());
+        info!("  Poster ID: {}", root.get_poster_id()?.to_str()?);
+        info!("  Number of Symbols: {}", root.get_symbol_infos()?.len());
+        println!("┌{:─^60}┐", "");
+        println!("│{: ^60}│", "OrderBook Archive");
+        println!("│{: ^60}│", format!("period: {}", root.get_period()));
+        println!("├{:─^60}┤", "");
+        println!("│{: <20}│{: >19}│{: >19}│", "Symbol", "Inc_Count", "Trade_Count");
+        println!("├{:─^60}┤", "");
+        for symbol in self.symbols()? {
+            println!("│{: <20}│{: >19}│{: >19}│",
+                symbol.symbol()?,
+                symbol.inc_count()?,
+                symbol.trade_count()?
+            );
+        }
+        println!("└{:─^60}┘", "");
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SymbolView<'a> {
+    reader: symbol_info::Reader<'a>,
+}
+
+impl<'a> SymbolView<'a> {
+    pub fn symbol(&self) -> Result<&'a str> {
+        Ok(self.reader.get_symbol()?.to_str()?)
+    }
+
+    pub fn trade_count(&self) -> Result<usize> {
+        Ok(self.reader.get_trades()?.len() as usize)
+    }
+
+    pub fn inc_count(&self) -> Result<usize> {
+        Ok(self.reader.get_incs()?.len() as usize)
+    }
+
+    pub fn trades(&self) -> Result<impl Iterator<Item = TradeView<'a>>> {
+        Ok(self.reader.get_trades()?.iter().map(|reader| TradeView { reader }))
+    }
+
+    pub fn incs(&self) -> Result<impl Iterator<Item = IncView<'a>>> {
+        Ok(self.reader.get_incs()?.iter().map(|reader| IncView { reader }))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct TradeView<'a> {
+    reader: trade_info::Reader<'a>,
+}
+
+impl<'a> TradeView<'a> {
+    pub fn timestamp(&self) -> i64 {
+        self.reader.get_timestamp()
+    }
+
+    pub fn side(&self) -> Result<Side> {
+        Ok(Side::parse(self.raw_side()?))
+    }
+
+    /// 线上格式中的原始字符串
+    pub fn raw_side(&self) -> Result<&'a str> {
+        Ok(self.reader.get_side()?.to_str()?)
+    }
+
+    pub fn price(&self) -> f64 {
+        self.reader.get_price()
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.reader.get_amount()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct IncView<'a> {
+    reader: increment_order_book_info::Reader<'a>,
+}
+
+impl<'a> IncView<'a> {
+    pub fn timestamp(&self) -> i64 {
+        self.reader.get_timestamp()
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        self.reader.get_is_snapshot()
+    }
+
+    pub fn bids(&self) -> Result<impl Iterator<Item = LevelView<'a>>> {
+        Ok(self.reader.get_bids()?.iter().map(|reader| LevelView { reader }))
+    }
+
+    pub fn asks(&self) -> Result<impl Iterator<Item = LevelView<'a>>> {
+        Ok(self.reader.get_asks()?.iter().map(|reader| LevelView { reader }))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LevelView<'a> {
+    reader: price_level::Reader<'a>,
+}
+
+impl LevelView<'_> {
+    pub fn price(&self) -> f64 {
+        self.reader.get_price()
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.reader.get_amount()
+    }
+
+    /// 同 [`PriceLevel::action`](crate::PriceLevel::action)
+    pub fn action(&self) -> LevelAction {
+        PriceLevel { price: self.price(), amount: self.amount() }.action()
+    }
+}
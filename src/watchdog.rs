@@ -0,0 +1,208 @@
+// 上游断流看门狗: 按 period 节奏检查各交易所是否还有新数据, 并提供健康检查接口
+use anyhow::Result;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::WatchdogConfig;
+use crate::{MktArchiveMsg, RedisStreamMktPubber};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedAlarm {
+    // 超时没有收到任何消息
+    Silent,
+    // 仍在收到消息, 但 period 不再增长
+    Stalled,
+}
+
+impl FeedAlarm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeedAlarm::Silent => "silent",
+            FeedAlarm::Stalled => "stalled",
+        }
+    }
+}
+
+/// 健康检查接口中单个交易所的状态
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedStatus {
+    pub exchange: String,
+    pub healthy: bool,
+    pub alarm: Option<FeedAlarm>,
+    pub last_period: Option<i64>,
+    pub secs_since_last_message: Option<u64>,
+    pub secs_since_last_new_period: u64,
+}
+
+struct FeedState {
+    last_period: Option<i64>,
+    last_message: Option<Instant>,
+    // 最近一次 period 增长的时间, 启动时为创建时间
+    last_advance: Instant,
+    alarm: Option<FeedAlarm>,
+}
+
+pub struct FeedWatchdog {
+    exchange: String,
+    timeout: Duration,
+    state: Mutex<FeedState>,
+}
+
+impl FeedWatchdog {
+    pub fn new(exchange: &str, cfg: &WatchdogConfig) -> Self {
+        Self {
+            exchange: exchange.to_string(),
+            timeout: Duration::from_secs(cfg.period_interval_secs) * cfg.silence_intervals,
+            state: Mutex::new(FeedState {
+                last_period: None,
+                last_message: None,
+                last_advance: Instant::now(),
+                alarm: None,
+            }),
+        }
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    /// 每收到一条解码成功的消息调用一次
+    pub fn observe(&self, period: i64) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.last_message = Some(now);
+        if state.last_period.is_none_or(|last| period > last) {
+            state.last_period = Some(period);
+            state.last_advance = now;
+            if let Some(alarm) = state.alarm.take() {
+                info!("[{}] 行情恢复 ({} 已解除), 最新 period: {}", self.exchange, alarm.as_str(), period);
+            }
+        }
+    }
+
+    /// 检查是否超时, 告警状态变化时记录日志, 返回当前告警
+    pub fn check(&self) -> Option<FeedAlarm> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.last_advance) < self.timeout {
+            return None;
+        }
+        let silent = state
+            .last_message
+            .is_none_or(|t| now.duration_since(t) >= self.timeout);
+        let alarm = if silent { FeedAlarm::Silent } else { FeedAlarm::Stalled };
+        if state.alarm != Some(alarm) {
+            match alarm {
+                FeedAlarm::Silent => error!(
+                    "[{}] 超过 {:?} 未收到上游消息, 最后的 period: {:?}",
+                    self.exchange, self.timeout, state.last_period
+                ),
+                FeedAlarm::Stalled => error!(
+                    "[{}] 超过 {:?} period 未增长, 停留在: {:?}",
+                    self.exchange, self.timeout, state.last_period
+                ),
+            }
+            state.alarm = Some(alarm);
+        }
+        Some(alarm)
+    }
+
+    pub fn status(&self) -> FeedStatus {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        FeedStatus {
+            exchange: self.exchange.clone(),
+            healthy: state.alarm.is_none(),
+            alarm: state.alarm,
+            last_period: state.last_period,
+            secs_since_last_message: state.last_message.map(|t| now.duration_since(t).as_secs()),
+            secs_since_last_new_period: now.duration_since(state.last_advance).as_secs(),
+        }
+    }
+
+    fn last_period(&self) -> Option<i64> {
+        self.state.lock().unwrap().last_period
+    }
+}
+
+/// 每个 period 间隔检查一次所有交易所; 开启 `heartbeat` 时告警期间向心跳 Stream 写标记
+pub async fn run_watchdog(
+    publisher: Arc<RedisStreamMktPubber>,
+    watchdogs: Vec<Arc<FeedWatchdog>>,
+    cfg: WatchdogConfig,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(cfg.period_interval_secs));
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        for watchdog in &watchdogs {
+            let Some(alarm) = watchdog.check() else {
+                continue;
+            };
+            if !cfg.heartbeat {
+                continue;
+            }
+            let msg = MktArchiveMsg::heartbeat(watchdog.last_period(), alarm.as_str());
+            if let Err(e) = publisher.publish(watchdog.exchange(), msg).await {
+                warn!("[{}] 写入心跳标记失败: {}", watchdog.exchange(), e);
+            }
+        }
+    }
+}
+
+/// 健康检查 HTTP 服务, 所有交易所正常时返回 200, 否则 503, 响应体为各交易所状态的 JSON
+pub async fn serve_health(addr: &str, watchdogs: Vec<Arc<FeedWatchdog>>, shutdown: CancellationToken) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("健康检查服务监听: {}", addr);
+    loop {
+        let (stream, _) = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok(a) => a,
+                Err(e) => {
+                    warn!("健康检查连接失败: {}", e);
+                    continue;
+                }
+            },
+        };
+        let statuses: Vec<FeedStatus> = watchdogs.iter().map(|w| w.status()).collect();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, statuses).await {
+                warn!("健康检查响应失败: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, statuses: Vec<FeedStatus>) -> Result<()> {
+    // 只需要读掉请求头, 不区分路径
+    let mut buf = [0u8; 1024];
+    let _ = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf)).await;
+
+    let healthy = statuses.iter().all(|s| s.healthy);
+    let body = serde_json::json!({
+        "status": if healthy { "ok" } else { "degraded" },
+        "exchanges": statuses,
+    })
+    .to_string();
+    let status_line = if healthy { "200 OK" } else { "503 Service Unavailable" };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}